@@ -1,7 +1,24 @@
-use mnist::{Mnist, MnistBuilder};
-use ndarray::{Array2, Array3};
+use std::{
+    mem,
+    ops::Range,
+    path::{Path, PathBuf},
+};
 
+use ndarray::{Array2, Array3, ArrayD, Ix2, Ix3, IxDyn};
+
+use crate::dataset::{
+    data_loader::{ArrayDataset, Split},
+    error::DatasetError,
+    idx::{find_idx, load_idx},
+};
+
+/// Train, validation and test splits. Images come flattened (`*_2d`, (N, H*W)) and as images
+/// (`*_3d`, (N, H, W)); the layouts not selected with `MnistLoader::with_layout` are left empty
+/// (0 rows).
 pub struct MnistDataset {
+    /// Name of every class, indexed by label.
+    pub class_names: &'static [&'static str],
+
     pub x_train_2d: Array2<f64>,
     pub x_train_3d: Array3<f64>,
     pub t_train: Array2<f64>,
@@ -15,129 +32,580 @@ pub struct MnistDataset {
     pub t_test: Array2<f64>,
 }
 
-pub fn load_mnist(
-    (train_length, validation_length, test_length): (u32, u32, u32),
-    normalize: bool,
-    one_hot_encoding: bool,
-) -> MnistDataset {
-    let mut mnist_builder = MnistBuilder::new();
-    mnist_builder
-        .base_path("data/")
-        .training_set_length(train_length)
-        .validation_set_length(validation_length)
-        .test_set_length(test_length);
-
-    if one_hot_encoding {
-        mnist_builder.label_format_one_hot();
-    }
-
-    let Mnist {
-        trn_img,
-        trn_lbl,
-        val_img,
-        val_lbl,
-        tst_img,
-        tst_lbl,
-        ..
-    } = mnist_builder.finalize();
-
-    let x_train_2d = Array2::from_shape_vec((train_length as usize, 784), trn_img.clone())
-        .expect("Error converting images to Array2 struct")
-        .map(|x| {
-            if normalize {
-                *x as f64 / 256.0
-            } else {
-                *x as f64
-            }
-        });
-
-    let x_train_3d = Array3::from_shape_vec((train_length as usize, 28, 28), trn_img)
-        .expect("Error converting images to Array3 struct")
-        .map(|x| {
-            if normalize {
-                *x as f64 / 256.0
-            } else {
-                *x as f64
+impl MnistDataset {
+    /// Move a split with flattened images out for `DataLoader`, leaving empty arrays behind.
+    /// The labels go with it, so take one layout per split.
+    pub fn take_dataset_2d(&mut self, split: Split) -> ArrayDataset<Ix2> {
+        let (x, t) = match split {
+            Split::Train => (&mut self.x_train_2d, &mut self.t_train),
+            Split::Validation => (&mut self.x_val_2d, &mut self.t_val),
+            Split::Test => (&mut self.x_test_2d, &mut self.t_test),
+        };
+        ArrayDataset::new(mem::take(x), mem::take(t))
+    }
+
+    /// Like `take_dataset_2d`, with images of shape (H, W).
+    pub fn take_dataset_3d(&mut self, split: Split) -> ArrayDataset<Ix3> {
+        let (x, t) = match split {
+            Split::Train => (&mut self.x_train_3d, &mut self.t_train),
+            Split::Validation => (&mut self.x_val_3d, &mut self.t_val),
+            Split::Test => (&mut self.x_test_3d, &mut self.t_test),
+        };
+        ArrayDataset::new(mem::take(x), mem::take(t))
+    }
+}
+
+const DIGITS: [&str; 10] = ["0", "1", "2", "3", "4", "5", "6", "7", "8", "9"];
+
+const FASHION_MNIST_CLASSES: [&str; 10] = [
+    "T-shirt/top",
+    "Trouser",
+    "Pullover",
+    "Dress",
+    "Coat",
+    "Sandal",
+    "Shirt",
+    "Sneaker",
+    "Bag",
+    "Ankle boot",
+];
+
+/// Romanized hiragana of Kuzushiji-MNIST.
+const KMNIST_CLASSES: [&str; 10] = ["o", "ki", "su", "tsu", "na", "ha", "ma", "ya", "re", "wo"];
+
+const EMNIST_LETTERS_CLASSES: [&str; 26] = [
+    "A", "B", "C", "D", "E", "F", "G", "H", "I", "J", "K", "L", "M", "N", "O", "P", "Q", "R", "S",
+    "T", "U", "V", "W", "X", "Y", "Z",
+];
+
+/// Digits, upper-case letters and the lower-case letters that do not look like their capitals.
+const EMNIST_BALANCED_CLASSES: [&str; 47] = [
+    "0", "1", "2", "3", "4", "5", "6", "7", "8", "9", "A", "B", "C", "D", "E", "F", "G", "H", "I",
+    "J", "K", "L", "M", "N", "O", "P", "Q", "R", "S", "T", "U", "V", "W", "X", "Y", "Z", "a", "b",
+    "d", "e", "f", "g", "h", "n", "q", "r", "t",
+];
+
+/// Datasets distributed as 28x28 grayscale images in MNIST's IDX layout.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MnistVariant {
+    #[default]
+    Mnist,
+    /// Zalando's article images (https://github.com/zalandoresearch/fashion-mnist)
+    FashionMnist,
+    /// Kuzushiji-MNIST, cursive Japanese (https://github.com/rois-codh/kmnist)
+    Kmnist,
+    /// Extended MNIST, handwritten letters and digits
+    /// (https://www.nist.gov/itl/products-and-services/emnist-dataset)
+    Emnist(EmnistSplit),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EmnistSplit {
+    /// 47 classes with the same number of samples each.
+    Balanced,
+    /// 26 case-insensitive letters.
+    Letters,
+    /// 10 digits.
+    Digits,
+}
+
+impl MnistVariant {
+    pub fn class_names(&self) -> &'static [&'static str] {
+        match self {
+            Self::Mnist | Self::Emnist(EmnistSplit::Digits) => &DIGITS,
+            Self::FashionMnist => &FASHION_MNIST_CLASSES,
+            Self::Kmnist => &KMNIST_CLASSES,
+            Self::Emnist(EmnistSplit::Balanced) => &EMNIST_BALANCED_CLASSES,
+            Self::Emnist(EmnistSplit::Letters) => &EMNIST_LETTERS_CLASSES,
+        }
+    }
+
+    fn default_data_dir(&self) -> &'static str {
+        match self {
+            Self::Mnist => "data/",
+            Self::FashionMnist => "data/fashion-mnist/",
+            Self::Kmnist => "data/kmnist/",
+            Self::Emnist(_) => "data/emnist/",
+        }
+    }
+
+    /// Accepted names of the (images, labels) files of the training or test set.
+    fn file_names(&self, train: bool) -> (Vec<String>, Vec<String>) {
+        let set = if train { "train" } else { "t10k" };
+        match self {
+            Self::Emnist(split) => {
+                let split = match split {
+                    EmnistSplit::Balanced => "balanced",
+                    EmnistSplit::Letters => "letters",
+                    EmnistSplit::Digits => "digits",
+                };
+                let set = if train { "train" } else { "test" };
+                (
+                    vec![format!("emnist-{split}-{set}-images-idx3-ubyte")],
+                    vec![format!("emnist-{split}-{set}-labels-idx1-ubyte")],
+                )
             }
-        });
-
-    let t_train: Array2<f64> = if one_hot_encoding {
-        Array2::from_shape_vec((train_length as usize, 10), trn_lbl)
-    } else {
-        Array2::from_shape_vec((train_length as usize, 1), trn_lbl)
-    }
-    .expect("Error converting training labels to Array2 struct")
-    .map(|x| *x as f64);
-    // println!(
-    //     "The first digit is a {:?}",
-    //     t_train.slice(s![image_num, ..])
-    // );
-
-    let x_val_2d = Array2::from_shape_vec((validation_length as usize, 784), val_img.clone())
-        .expect("Error converting images to Array2 struct")
-        .map(|x| {
-            if normalize {
-                *x as f64 / 256.0
-            } else {
-                *x as f64
+            _ => (
+                vec![
+                    format!("{set}-images-idx3-ubyte"),
+                    format!("{set}-images.idx3-ubyte"),
+                ],
+                vec![
+                    format!("{set}-labels-idx1-ubyte"),
+                    format!("{set}-labels.idx1-ubyte"),
+                ],
+            ),
+        }
+    }
+
+    /// Value of the first label in the files. EMNIST letters count from 1.
+    fn first_label(&self) -> u8 {
+        match self {
+            Self::Emnist(EmnistSplit::Letters) => 1,
+            _ => 0,
+        }
+    }
+
+    /// EMNIST stores every image transposed.
+    fn transposed(&self) -> bool {
+        matches!(self, Self::Emnist(_))
+    }
+}
+
+/// How raw pixels in 0..=255 are scaled.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Normalization {
+    /// Raw pixel values.
+    None,
+    /// `x / 255`
+    #[default]
+    ZeroOne,
+    /// `(x / 255 - mean) / std`
+    MeanStd { mean: f64, std: f64 },
+    /// Like `MeanStd`, with the mean and standard deviation of the training split.
+    Standardize,
+}
+
+impl Normalization {
+    pub(crate) fn validate(&self) -> Result<(), DatasetError> {
+        match *self {
+            Self::MeanStd { std, .. } if std.is_nan() || std <= 0. => Err(
+                DatasetError::InvalidConfig(format!("std must be positive, got {std}")),
+            ),
+            _ => Ok(()),
+        }
+    }
+
+    /// Mean and standard deviation in raw pixel units, so that `(x - mean) / std` normalizes.
+    /// `train_pixels` is only looked at for `Standardize`, which fails if they do not vary.
+    pub(crate) fn pixel_stats(&self, train_pixels: &[u8]) -> Result<(f64, f64), DatasetError> {
+        Ok(match *self {
+            Self::None => (0., 1.),
+            Self::ZeroOne => (0., 255.),
+            Self::MeanStd { mean, std } => (mean * 255., std * 255.),
+            Self::Standardize => {
+                if train_pixels.is_empty() {
+                    return Err(DatasetError::InvalidConfig(
+                        "cannot standardize with an empty training split".to_owned(),
+                    ));
+                }
+                let n = train_pixels.len() as f64;
+                let mean = train_pixels.iter().map(|&x| x as f64).sum::<f64>() / n;
+                let var = train_pixels
+                    .iter()
+                    .map(|&x| (x as f64 - mean).powi(2))
+                    .sum::<f64>()
+                    / n;
+                if var == 0. {
+                    return Err(DatasetError::InvalidConfig(format!(
+                        "cannot standardize: every training pixel is {mean}"
+                    )));
+                }
+                (mean, var.sqrt())
             }
-        });
-
-    let x_val_3d = Array3::from_shape_vec((validation_length as usize, 28, 28), val_img)
-        .expect("Error converting images to Array3 struct")
-        .map(|x| {
-            if normalize {
-                *x as f64 / 256.0
-            } else {
-                *x as f64
+        })
+    }
+}
+
+/// How labels are encoded in `t_*`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum LabelFormat {
+    /// (N, 1) class indices.
+    Index,
+    /// (N, classes)
+    #[default]
+    OneHot,
+    /// One-hot smoothed by `epsilon`: `1 - epsilon + epsilon / classes` for the true class,
+    /// `epsilon / classes` elsewhere.
+    Smoothed(f64),
+}
+
+impl LabelFormat {
+    pub(crate) fn validate(&self) -> Result<(), DatasetError> {
+        match *self {
+            Self::Smoothed(epsilon) if !(0. ..1.).contains(&epsilon) => Err(
+                DatasetError::InvalidConfig(format!("epsilon must be in [0, 1), got {epsilon}")),
+            ),
+            _ => Ok(()),
+        }
+    }
+
+    /// Encode class indices below `classes`.
+    pub(crate) fn encode(&self, labels: &[u8], classes: usize) -> Array2<f64> {
+        let (on, off) = match *self {
+            Self::Index => {
+                return Array2::from_shape_fn((labels.len(), 1), |(i, _)| labels[i] as f64);
             }
-        });
-
-    let t_val: Array2<f64> = if one_hot_encoding {
-        Array2::from_shape_vec((validation_length as usize, 10), val_lbl)
-    } else {
-        Array2::from_shape_vec((validation_length as usize, 1), val_lbl)
-    }
-    .expect("Error converting validation labels to Array2 struct")
-    .map(|x| *x as f64);
-
-    let x_test_2d = Array2::from_shape_vec((test_length as usize, 784), tst_img.clone())
-        .expect("Error converting images to Array2 struct")
-        .map(|x| {
-            if normalize {
-                *x as f64 / 256.0
-            } else {
-                *x as f64
+            Self::OneHot => (1., 0.),
+            Self::Smoothed(epsilon) => {
+                let off = epsilon / classes as f64;
+                (1. - epsilon + off, off)
             }
-        });
-
-    let x_test_3d = Array3::from_shape_vec((test_length as usize, 28, 28), tst_img)
-        .expect("Error converting images to Array3 struct")
-        .map(|x| {
-            if normalize {
-                *x as f64 / 256.0
-            } else {
-                *x as f64
+        };
+        Array2::from_shape_fn((labels.len(), classes), |(i, j)| {
+            if labels[i] as usize == j { on } else { off }
+        })
+    }
+}
+
+/// Requested split sizes. The validation split follows the training split in the training set;
+/// `None` takes every remaining sample.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) struct SplitSizes {
+    pub train: Option<usize>,
+    pub validation: usize,
+    pub test: Option<usize>,
+}
+
+impl SplitSizes {
+    /// Sample ranges of the (train, validation, test) splits, given the sizes of the training
+    /// and test sets.
+    pub(crate) fn resolve(
+        &self,
+        train_available: usize,
+        test_available: usize,
+    ) -> Result<[Range<usize>; 3], DatasetError> {
+        let train = self
+            .train
+            .unwrap_or(train_available.saturating_sub(self.validation));
+        if train + self.validation > train_available {
+            return Err(DatasetError::SplitTooLarge {
+                split: "training and validation",
+                requested: train + self.validation,
+                available: train_available,
+            });
+        }
+        let test = self.test.unwrap_or(test_available);
+        if test > test_available {
+            return Err(DatasetError::SplitTooLarge {
+                split: "test",
+                requested: test,
+                available: test_available,
+            });
+        }
+        Ok([0..train, train..train + self.validation, 0..test])
+    }
+}
+
+/// Which image layouts of `MnistDataset` are filled.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ImageLayout {
+    /// `x_*_2d` only
+    Flat,
+    /// `x_*_3d` only
+    Image,
+    #[default]
+    Both,
+}
+
+/// Loads MNIST or one of its variants from IDX files, uncompressed or gzipped. The files are
+/// looked for in `data/` for MNIST and in `data/<variant>/` (e.g. `data/fashion-mnist/`) for
+/// the others, under their original names.
+///
+/// The validation split is taken from the training images that follow the first `train_size`.
+/// By default every training image goes to the training split, and the test split holds the
+/// whole test set.
+#[derive(Clone, Debug)]
+pub struct MnistLoader {
+    variant: MnistVariant,
+    data_dir: Option<PathBuf>,
+    split_sizes: SplitSizes,
+    normalization: Normalization,
+    label_format: LabelFormat,
+    layout: ImageLayout,
+}
+
+impl Default for MnistLoader {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MnistLoader {
+    pub fn new() -> Self {
+        Self {
+            variant: MnistVariant::default(),
+            data_dir: None,
+            split_sizes: SplitSizes::default(),
+            normalization: Normalization::default(),
+            label_format: LabelFormat::default(),
+            layout: ImageLayout::default(),
+        }
+    }
+
+    pub fn with_variant(mut self, variant: MnistVariant) -> Self {
+        self.variant = variant;
+        self
+    }
+
+    /// Replace the variant's default directory.
+    pub fn with_data_dir(mut self, data_dir: impl Into<PathBuf>) -> Self {
+        self.data_dir = Some(data_dir.into());
+        self
+    }
+
+    /// `None` for `train` or `test` takes every remaining sample.
+    pub fn with_split_sizes(
+        mut self,
+        train: Option<usize>,
+        validation: usize,
+        test: Option<usize>,
+    ) -> Self {
+        self.split_sizes = SplitSizes {
+            train,
+            validation,
+            test,
+        };
+        self
+    }
+
+    /// Checked by `load`.
+    pub fn with_normalization(mut self, normalization: Normalization) -> Self {
+        self.normalization = normalization;
+        self
+    }
+
+    /// Checked by `load`.
+    pub fn with_label_format(mut self, label_format: LabelFormat) -> Self {
+        self.label_format = label_format;
+        self
+    }
+
+    pub fn with_layout(mut self, layout: ImageLayout) -> Self {
+        self.layout = layout;
+        self
+    }
+
+    pub fn load(&self) -> Result<MnistDataset, DatasetError> {
+        self.normalization.validate()?;
+        self.label_format.validate()?;
+        let (train_images, train_labels) = self.load_pair(true)?;
+        let (test_images, test_labels) = self.load_pair(false)?;
+
+        let [train, validation, test] = self
+            .split_sizes
+            .resolve(train_labels.len(), test_labels.len())?;
+
+        let train_pixels =
+            &train_images.as_slice().unwrap()[pixel_range(&train_images, train.clone())];
+        let (mean, std) = self.normalization.pixel_stats(train_pixels)?;
+        let classes = self.variant.class_names().len();
+        let split = |images: &ArrayD<u8>, labels: &[u8], range: Range<usize>| {
+            let (x_2d, x_3d) = self.images(images, range.clone(), mean, std);
+            let t = self.label_format.encode(&labels[range], classes);
+            (x_2d, x_3d, t)
+        };
+        let (x_train_2d, x_train_3d, t_train) = split(&train_images, &train_labels, train);
+        let (x_val_2d, x_val_3d, t_val) = split(&train_images, &train_labels, validation);
+        let (x_test_2d, x_test_3d, t_test) = split(&test_images, &test_labels, test);
+
+        Ok(MnistDataset {
+            class_names: self.variant.class_names(),
+            x_train_2d,
+            x_train_3d,
+            t_train,
+            x_val_2d,
+            x_val_3d,
+            t_val,
+            x_test_2d,
+            x_test_3d,
+            t_test,
+        })
+    }
+
+    /// (N, H, W) images of the training or test set and their N labels counting from 0,
+    /// checked against each other.
+    fn load_pair(&self, train: bool) -> Result<(ArrayD<u8>, Vec<u8>), DatasetError> {
+        let data_dir = match &self.data_dir {
+            Some(data_dir) => data_dir.as_path(),
+            None => Path::new(self.variant.default_data_dir()),
+        };
+        let (images, labels) = self.variant.file_names(train);
+        let (images_path, images) = load_u8(data_dir, &images)?;
+        let (labels_path, labels) = load_u8(data_dir, &labels)?;
+        if images.ndim() != 3 {
+            return Err(DatasetError::malformed(
+                images_path,
+                format!(
+                    "expected (N, H, W) images, found shape {:?}",
+                    images.shape()
+                ),
+            ));
+        }
+        if labels.ndim() != 1 {
+            return Err(DatasetError::malformed(
+                labels_path,
+                format!("expected (N,) labels, found shape {:?}", labels.shape()),
+            ));
+        }
+        if labels.len() != images.shape()[0] {
+            return Err(DatasetError::malformed(
+                labels_path,
+                format!(
+                    "{} labels for {} images in {}",
+                    labels.len(),
+                    images.shape()[0],
+                    images_path.display()
+                ),
+            ));
+        }
+        let first_label = self.variant.first_label();
+        let classes = self.variant.class_names().len();
+        if let Some(&label) = labels
+            .iter()
+            .find(|&&label| label < first_label || (label - first_label) as usize >= classes)
+        {
+            return Err(DatasetError::malformed(
+                labels_path,
+                format!(
+                    "label {label} is outside {first_label}..{}",
+                    first_label as usize + classes
+                ),
+            ));
+        }
+
+        let images = if self.variant.transposed() {
+            images
+                .permuted_axes(IxDyn(&[0, 2, 1]))
+                .as_standard_layout()
+                .into_owned()
+        } else {
+            images
+        };
+        let labels = labels.iter().map(|&label| label - first_label).collect();
+        Ok((images, labels))
+    }
+
+    fn images(
+        &self,
+        images: &ArrayD<u8>,
+        range: Range<usize>,
+        mean: f64,
+        std: f64,
+    ) -> (Array2<f64>, Array3<f64>) {
+        let (h, w) = (images.shape()[1], images.shape()[2]);
+        let n = range.len();
+        let pixels = images.as_slice().unwrap()[pixel_range(images, range)]
+            .iter()
+            .map(|&x| (x as f64 - mean) / std)
+            .collect::<Vec<_>>();
+        let x_2d = Array2::from_shape_vec((n, h * w), pixels).unwrap();
+        match self.layout {
+            ImageLayout::Flat => (x_2d, Array3::zeros((0, h, w))),
+            ImageLayout::Image => (
+                Array2::zeros((0, h * w)),
+                x_2d.into_shape_with_order((n, h, w)).unwrap(),
+            ),
+            ImageLayout::Both => {
+                let x_3d = x_2d.clone().into_shape_with_order((n, h, w)).unwrap();
+                (x_2d, x_3d)
             }
-        });
-
-    let t_test: Array2<f64> = if one_hot_encoding {
-        Array2::from_shape_vec((test_length as usize, 10), tst_lbl)
-    } else {
-        Array2::from_shape_vec((test_length as usize, 1), tst_lbl)
-    }
-    .expect("Error converting testing labels to Array2 struct")
-    .map(|x| *x as f64);
-
-    MnistDataset {
-        x_train_2d,
-        x_train_3d,
-        t_train,
-        x_val_2d,
-        x_val_3d,
-        t_val,
-        x_test_2d,
-        x_test_3d,
-        t_test,
+        }
+    }
+}
+
+/// Indices into the flat pixel buffer of the images in `range`.
+fn pixel_range(images: &ArrayD<u8>, range: Range<usize>) -> Range<usize> {
+    let image_size = images.shape()[1..].iter().product::<usize>();
+    range.start * image_size..range.end * image_size
+}
+
+/// An IDX file of unsigned bytes in `dir`, under any of `names` (optionally gzipped).
+fn load_u8(dir: &Path, names: &[String]) -> Result<(PathBuf, ArrayD<u8>), DatasetError> {
+    let names = names.iter().map(String::as_str).collect::<Vec<_>>();
+    let path = find_idx(dir, &names)?;
+    let array = load_idx(&path)?;
+    match array.into_u8() {
+        Some(array) => Ok((path, array)),
+        None => Err(DatasetError::malformed(path, "expected unsigned bytes")),
+    }
+}
+
+/// Shorthand for `MnistLoader` reading from `data/` with both image layouts. Pixels are scaled
+/// to 0..=1 when `normalize` is set.
+///
+/// Panics with the name of the file if one is missing or malformed.
+pub fn load_mnist(
+    (train_length, validation_length, test_length): (u32, u32, u32),
+    normalize: bool,
+    one_hot_encoding: bool,
+) -> MnistDataset {
+    MnistLoader::new()
+        .with_split_sizes(
+            Some(train_length as usize),
+            validation_length as usize,
+            Some(test_length as usize),
+        )
+        .with_normalization(if normalize {
+            Normalization::ZeroOne
+        } else {
+            Normalization::None
+        })
+        .with_label_format(if one_hot_encoding {
+            LabelFormat::OneHot
+        } else {
+            LabelFormat::Index
+        })
+        .load()
+        .unwrap_or_else(|err| panic!("failed to load MNIST: {err}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn standardize_rejects_pixels_that_do_not_vary() {
+        let err = Normalization::Standardize.pixel_stats(&[]).unwrap_err();
+        assert!(matches!(err, DatasetError::InvalidConfig(_)), "{err}");
+        let err = Normalization::Standardize
+            .pixel_stats(&[7; 16])
+            .unwrap_err();
+        assert!(
+            err.to_string().contains("every training pixel is 7"),
+            "{err}"
+        );
+
+        let (mean, std) = Normalization::Standardize.pixel_stats(&[0, 2]).unwrap();
+        assert_eq!((mean, std), (1., 1.));
+    }
+
+    #[test]
+    fn load_reports_invalid_settings_before_reading_files() {
+        let loader = MnistLoader::new().with_data_dir("no/such/dir");
+        let err = loader
+            .clone()
+            .with_normalization(Normalization::MeanStd { mean: 0.5, std: 0. })
+            .load()
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("std must be positive"), "{err}");
+
+        let err = loader
+            .with_label_format(LabelFormat::Smoothed(1.5))
+            .load()
+            .err()
+            .unwrap();
+        assert!(matches!(err, DatasetError::InvalidConfig(_)), "{err}");
     }
 }
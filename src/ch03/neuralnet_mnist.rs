@@ -1,49 +1,254 @@
+use std::{collections::HashMap, io, path::Path};
+
 use ndarray::*;
-use ndarray_rand::{RandomExt, rand_distr::StandardNormal};
 use ndarray_stats::QuantileExt;
 
-use crate::ch03::mnist_dataset::{MnistDataset, load_mnist};
+use crate::{
+    ch03::mnist_dataset::{MnistDataset, load_mnist},
+    ch04::{cross_entropy_error::cross_entropy_error, two_layer::Weight},
+    ch05::{
+        layers::{Affine, Layer, Sigmoid, SoftmaxWithLoss},
+        model::Model,
+    },
+    ch06::weight_init::WeightInit,
+    persistence::{npy::load_npz_params, params::load_params},
+};
 
 use super::{sigmoid::sigmoid, softmax_function::*};
 
-pub struct MnistNetwork {
-    w: Vec<Array2<f64>>,
-    b: Vec<Array1<f64>>,
+/// Trained weights of the three-layer network, if any have been saved.
+pub const SAMPLE_WEIGHT_PATH: &str = "data/sample_weight.params";
+/// The book's `sample_weight.pkl`, exported with
+/// `np.savez(path, **pickle.load(open("sample_weight.pkl", "rb")))`.
+pub const SAMPLE_WEIGHT_NPZ_PATH: &str = "data/sample_weight.npz";
+
+/// Weights and biases of the 784-50-100-10 network, shared by `MnistNetwork` and
+/// `MnistNetworkBatch`.
+#[derive(Clone, Debug)]
+pub struct SampleWeights {
+    pub w: Vec<Array2<f64>>,
+    pub b: Vec<Array1<f64>>,
 }
 
-impl MnistNetwork {
-    pub fn new() -> Self {
+impl SampleWeights {
+    pub fn new(weight_init: WeightInit) -> Self {
         let w = vec![
-            Array::random((784, 50), StandardNormal),
-            Array::random((50, 100), StandardNormal),
-            Array::random((100, 10), StandardNormal),
-        ];
-        let b = vec![
-            Array::random(50, StandardNormal),
-            Array::random(100, StandardNormal),
-            Array::random(10, StandardNormal),
+            weight_init.dense(784, 50),
+            weight_init.dense(50, 100),
+            weight_init.dense(100, 10),
         ];
-        MnistNetwork { w, b }
+        let b = vec![Array1::zeros(50), Array1::zeros(100), Array1::zeros(10)];
+        SampleWeights { w, b }
+    }
+
+    /// Weights from a parameter map with keys `w1..w3`/`b1..b3`, as in the book's `sample_weight.pkl`.
+    /// Fails if a key is missing or the shapes do not chain into a three-layer network.
+    pub fn from_params(params: &HashMap<String, Weight>) -> io::Result<Self> {
+        let (mut w, mut b) = (Vec::<Array2<f64>>::new(), Vec::new());
+        for i in 1..=3 {
+            let (wi, bi) = layer_params(params, i)?;
+            if let Some(prev) = w.last()
+                && prev.ncols() != wi.nrows()
+            {
+                return Err(invalid_data(format!(
+                    "w{i} has {} rows, but w{} has {} columns",
+                    wi.nrows(),
+                    i - 1,
+                    prev.ncols()
+                )));
+            }
+            w.push(wi.to_owned());
+            b.push(bi.to_owned());
+        }
+        Ok(SampleWeights { w, b })
+    }
+
+    /// Parameter map with keys `w1..w3`/`b1..b3`, the inverse of `from_params`.
+    pub fn into_params(self) -> HashMap<String, Weight> {
+        let mut params = HashMap::new();
+        for (i, (w, b)) in self.w.into_iter().zip(self.b).enumerate() {
+            params.insert(format!("w{}", i + 1), Weight::M2(w));
+            params.insert(format!("b{}", i + 1), Weight::M1(b));
+        }
+        params
     }
+
+    /// Load trained weights saved with `persistence::params::save_params`.
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::from_params(&load_params(path)?)
+    }
+
+    /// Load the book's pretrained weights from an `.npz` (see `SAMPLE_WEIGHT_NPZ_PATH`).
+    /// The keys `W1..W3` are matched case-insensitively; missing or misshapen ones fail like
+    /// `from_params`.
+    pub fn load_npz(path: impl AsRef<Path>) -> io::Result<Self> {
+        let params = load_npz_params(path)?
+            .into_iter()
+            .map(|(name, param)| (name.to_lowercase(), param))
+            .collect();
+        Self::from_params(&params)
+    }
+
+    /// Weights from `SAMPLE_WEIGHT_PATH`, else from `SAMPLE_WEIGHT_NPZ_PATH`, else untrained ones.
+    pub fn load_sample() -> Self {
+        Self::load(SAMPLE_WEIGHT_PATH)
+            .or_else(|err| {
+                println!("{SAMPLE_WEIGHT_PATH}: {err}");
+                Self::load_npz(SAMPLE_WEIGHT_NPZ_PATH)
+            })
+            .unwrap_or_else(|err| {
+                println!("{SAMPLE_WEIGHT_NPZ_PATH}: {err}, using untrained weights");
+                Self::new(WeightInit::XavierNormal)
+            })
+    }
+}
+
+fn invalid_data(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+/// `params[key]` as a `D`-dimensional view.
+fn param<'p, D: Dimension>(
+    params: &'p HashMap<String, Weight>,
+    key: &str,
+) -> io::Result<ArrayView<'p, f64, D>> {
+    let param = params
+        .get(key)
+        .ok_or_else(|| invalid_data(format!("no parameter `{key}`")))?
+        .view();
+    let shape = param.shape().to_vec();
+    param.into_dimensionality().map_err(|_| {
+        invalid_data(format!(
+            "`{key}` has shape {shape:?}, expected {} dimensions",
+            D::NDIM.unwrap()
+        ))
+    })
+}
+
+/// Weight and bias of layer `i` (1-based), a matrix and a vector as wide as it.
+fn layer_params(
+    params: &HashMap<String, Weight>,
+    i: usize,
+) -> io::Result<(ArrayView2<'_, f64>, ArrayView1<'_, f64>)> {
+    let w = param::<Ix2>(params, &format!("w{i}"))?;
+    let b = param::<Ix1>(params, &format!("b{i}"))?;
+    if b.len() != w.ncols() {
+        return Err(invalid_data(format!(
+            "b{i} has {} elements, but w{i} has {} columns",
+            b.len(),
+            w.ncols()
+        )));
+    }
+    Ok((w, b))
+}
+
+/// The layers of a network built from `SampleWeights`. `Model::load_params` keeps names and
+/// shapes, so only replacing parameters through `params_mut` can make this panic.
+pub(super) fn layers(
+    params: &HashMap<String, Weight>,
+) -> [(ArrayView2<'_, f64>, ArrayView1<'_, f64>); 3] {
+    [1, 2, 3].map(|i| layer_params(params, i).unwrap_or_else(|err| panic!("{err}")))
+}
+
+/// Gradients of the cross entropy error by backpropagation, keyed like `params`.
+pub(super) fn sample_gradient(
+    params: &HashMap<String, Weight>,
+    x: &Array2<f64>,
+    t: &Array2<f64>,
+) -> HashMap<String, Weight> {
+    let mut affines = layers(params).map(|(w, b)| Affine::new(w.to_owned(), b.to_owned()));
+    let mut sigmoids = [Sigmoid::new(), Sigmoid::new()];
+    let mut last_layer = SoftmaxWithLoss::new();
+
+    // forward
+    let mut x = x.clone();
+    for (affine, sigmoid) in affines.iter_mut().zip(sigmoids.iter_mut()) {
+        x = sigmoid.forward(&affine.forward(&x));
+    }
+    last_layer.forward(&affines[2].forward(&x), t);
+
+    // backward
+    let mut dout = affines[2].backward(&last_layer.backward(1.));
+    for (affine, sigmoid) in affines.iter_mut().zip(sigmoids.iter_mut()).rev() {
+        dout = affine.backward(&sigmoid.backward(&dout));
+    }
+
+    let mut grads = HashMap::new();
+    for (i, affine) in affines.iter().enumerate() {
+        for (name, grad) in affine.grads() {
+            grads.insert(format!("{name}{}", i + 1), grad);
+        }
+    }
+    grads
+}
+
+/// Predicts one sample at a time.
+pub struct MnistNetwork {
+    params: HashMap<String, Weight>,
+}
+
+impl From<SampleWeights> for MnistNetwork {
+    fn from(weights: SampleWeights) -> Self {
+        MnistNetwork {
+            params: weights.into_params(),
+        }
+    }
+}
+
+impl MnistNetwork {
+    pub fn new(weight_init: WeightInit) -> Self {
+        SampleWeights::new(weight_init).into()
+    }
+
     fn predict(&self, x: &Array1<f64>) -> Array1<f64> {
-        let a1 = x.dot(&self.w[0]) + &self.b[0];
+        let [(w1, b1), (w2, b2), (w3, b3)] = layers(&self.params);
+        let a1 = x.dot(&w1) + b1;
         let z1 = sigmoid(&a1);
-        let a2 = z1.dot(&self.w[1]) + &self.b[1];
+        let a2 = z1.dot(&w2) + b2;
         let z2 = sigmoid(&a2);
-        let a3 = z2.dot(&self.w[2]) + &self.b[2];
+        let a3 = z2.dot(&w3) + b3;
         let y = softmax(&a3);
 
         y
     }
 }
 
+impl Model for MnistNetwork {
+    type Input = Array2<f64>;
+
+    fn params(&self) -> &HashMap<String, Weight> {
+        &self.params
+    }
+
+    fn params_mut(&mut self) -> &mut HashMap<String, Weight> {
+        &mut self.params
+    }
+
+    /// Runs `MnistNetwork::predict` on every row.
+    fn predict(&mut self, x: &Array2<f64>) -> Array2<f64> {
+        let mut y = Array2::zeros((x.nrows(), layers(&self.params)[2].1.len()));
+        for (x, mut y) in x.rows().into_iter().zip(y.rows_mut()) {
+            y.assign(&MnistNetwork::predict(self, &x.to_owned()));
+        }
+        y
+    }
+
+    fn loss(&mut self, x: &Array2<f64>, t: &Array2<f64>) -> f64 {
+        cross_entropy_error(&Model::predict(self, x), t)
+    }
+
+    fn gradient(&mut self, x: &Array2<f64>, t: &Array2<f64>) -> HashMap<String, Weight> {
+        sample_gradient(&self.params, x, t)
+    }
+}
+
 pub fn run() {
     let MnistDataset {
         x_train_2d,
         t_train,
         ..
     } = load_mnist((60_000, 0, 10_000), true, true);
-    let network = MnistNetwork::new();
+    let network = MnistNetwork::from(SampleWeights::load_sample());
     let mut accuracy_cnt = 0;
     for i in 0..x_train_2d.nrows() {
         let y = network.predict(&x_train_2d.row(i).into_owned());
@@ -65,3 +270,125 @@ pub fn run() {
         accuracy_cnt as f64 / x_train_2d.nrows() as f64 * 100.
     );
 }
+
+#[cfg(test)]
+mod tests {
+    use ndarray_rand::{RandomExt, rand_distr::Uniform};
+
+    use crate::{
+        ch03::neuralnet_mnist_batch::MnistNetworkBatch, ch04::two_layer::TwoLayerNet,
+        persistence::npy::save_npz_params,
+    };
+
+    use super::*;
+
+    fn batch() -> (Array2<f64>, Array2<f64>) {
+        let x = Array::random((3, 784), Uniform::new(0., 1.));
+        let mut t = Array2::zeros((3, 10));
+        for (i, label) in [3, 0, 7].into_iter().enumerate() {
+            t[[i, label]] = 1.;
+        }
+        (x, t)
+    }
+
+    #[test]
+    fn both_networks_predict_the_same() {
+        let weights = SampleWeights::new(WeightInit::XavierNormal);
+        let mut network = MnistNetwork::from(weights.clone());
+        let mut network_batch = MnistNetworkBatch::from(weights);
+        let (x, t) = batch();
+
+        let (y, y_batch) = (
+            Model::predict(&mut network, &x),
+            Model::predict(&mut network_batch, &x),
+        );
+        assert!((&y - &y_batch).abs().iter().all(|&d| d < 1e-12));
+        assert_eq!(network.accuracy(&x, &t), network_batch.accuracy(&x, &t));
+    }
+
+    #[test]
+    fn gradient_matches_central_difference() {
+        let mut network = MnistNetwork::new(WeightInit::XavierNormal);
+        let (x, t) = batch();
+        let grads = network.gradient(&x, &t);
+
+        let h = 1e-4;
+        for key in ["w1", "b1", "w2", "b2", "w3", "b3"] {
+            let param = network.params()[key].view().to_owned();
+            for idx in indices_of(&param).into_iter().step_by(37).take(5) {
+                let v = param[&idx];
+                network.params_mut().get_mut(key).unwrap().view_mut()[&idx] = v + h;
+                let fxh1 = network.loss(&x, &t);
+                network.params_mut().get_mut(key).unwrap().view_mut()[&idx] = v - h;
+                let fxh2 = network.loss(&x, &t);
+                network.params_mut().get_mut(key).unwrap().view_mut()[&idx] = v;
+
+                let numerical = (fxh1 - fxh2) / (2. * h);
+                // backpropagation ignores the `delta` cross_entropy_error adds inside the log
+                let diff = (grads[key].view()[&idx] - numerical).abs();
+                assert!(diff < 1e-5, "{key}{idx:?}: {diff}");
+            }
+        }
+    }
+
+    #[test]
+    fn load_rejects_another_models_params() {
+        let path = std::env::temp_dir().join("sample_weight_test_two_layer.params");
+        TwoLayerNet::new(784, 50, 10, WeightInit::XavierNormal)
+            .save_params(&path)
+            .unwrap();
+        let err = SampleWeights::load(&path).unwrap_err();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains("w3"), "{err}");
+    }
+
+    #[test]
+    fn from_params_rejects_wrong_ranks_and_shapes() {
+        let mut params = SampleWeights::new(WeightInit::XavierNormal).into_params();
+        params.insert("w2".to_owned(), Weight::M2(Array2::zeros((40, 100))));
+        let err = SampleWeights::from_params(&params).unwrap_err();
+        assert!(err.to_string().contains("w2 has 40 rows"), "{err}");
+
+        params.insert("w2".to_owned(), Weight::M1(Array1::zeros(100)));
+        let err = SampleWeights::from_params(&params).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains("`w2` has shape [100]"), "{err}");
+    }
+
+    #[test]
+    fn load_npz_matches_keys_case_insensitively() {
+        let weights = SampleWeights::new(WeightInit::XavierNormal);
+        let mut params = HashMap::new();
+        for (i, (w, b)) in weights.w.iter().zip(&weights.b).enumerate() {
+            params.insert(format!("W{}", i + 1), Weight::M2(w.clone()));
+            params.insert(format!("b{}", i + 1), Weight::M1(b.clone()));
+        }
+        let path = std::env::temp_dir().join("sample_weight_test.npz");
+        save_npz_params(&path, &params).unwrap();
+        let loaded = SampleWeights::load_npz(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.w, weights.w);
+        assert_eq!(loaded.b, weights.b);
+    }
+
+    #[test]
+    fn load_npz_rejects_missing_keys_and_1d_weights() {
+        let path = std::env::temp_dir().join("sample_weight_test_missing.npz");
+        let mut params = SampleWeights::new(WeightInit::XavierNormal).into_params();
+        params.remove("b3");
+        save_npz_params(&path, &params).unwrap();
+        let err = SampleWeights::load_npz(&path).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains("no parameter `b3`"), "{err}");
+
+        params.insert("b3".to_owned(), Weight::M1(Array1::zeros(10)));
+        params.insert("W1".to_owned(), Weight::M1(Array1::zeros(784)));
+        params.remove("w1");
+        save_npz_params(&path, &params).unwrap();
+        let err = SampleWeights::load_npz(&path).unwrap_err();
+        std::fs::remove_file(&path).unwrap();
+        assert!(err.to_string().contains("`w1` has shape [784]"), "{err}");
+    }
+}
@@ -1,78 +1,108 @@
+use std::collections::HashMap;
+
 use ndarray::*;
-use ndarray_rand::{RandomExt, rand_distr::StandardNormal};
 use ndarray_stats::QuantileExt;
 
-use crate::ch03::mnist_dataset::{MnistDataset, load_mnist};
+use crate::{
+    ch03::mnist_dataset::{MnistDataset, load_mnist},
+    ch04::{cross_entropy_error::cross_entropy_error, two_layer::Weight},
+    ch05::model::Model,
+    ch06::weight_init::WeightInit,
+    dataset::data_loader::{ArrayDataset, DataLoader, Sampler},
+};
 
-use super::{sigmoid::sigmoid, softmax_function::softmax};
+use super::{
+    neuralnet_mnist::{SampleWeights, layers, sample_gradient},
+    sigmoid::sigmoid,
+    softmax_function::softmax,
+};
 
 pub struct MnistNetworkBatch {
-    w: Vec<Array2<f64>>,
-    b: Vec<Array1<f64>>,
+    params: HashMap<String, Weight>,
+}
+
+impl From<SampleWeights> for MnistNetworkBatch {
+    fn from(weights: SampleWeights) -> Self {
+        MnistNetworkBatch {
+            params: weights.into_params(),
+        }
+    }
 }
 
 impl MnistNetworkBatch {
-    pub fn new() -> Self {
-        let w = vec![
-            Array::random((784, 50), StandardNormal),
-            Array::random((50, 100), StandardNormal),
-            Array::random((100, 10), StandardNormal),
-        ];
-        let b = vec![
-            Array::random(50, StandardNormal),
-            Array::random(100, StandardNormal),
-            Array::random(10, StandardNormal),
-        ];
-        MnistNetworkBatch { w, b }
+    pub fn new(weight_init: WeightInit) -> Self {
+        SampleWeights::new(weight_init).into()
     }
+
     fn predict(&self, x: &Array2<f64>) -> Array2<f64> {
-        let a1 = x.dot(&self.w[0]) + &self.b[0];
+        let [(w1, b1), (w2, b2), (w3, b3)] = layers(&self.params);
+        let a1 = x.dot(&w1) + b1;
         let z1 = sigmoid(&a1);
-        let a2 = z1.dot(&self.w[1]) + &self.b[1];
+        let a2 = z1.dot(&w2) + b2;
         let z2 = sigmoid(&a2);
-        let a3 = z2.dot(&self.w[2]) + &self.b[2];
+        let a3 = z2.dot(&w3) + b3;
         let y = softmax(&a3);
 
         y
     }
 }
 
+impl Model for MnistNetworkBatch {
+    type Input = Array2<f64>;
+
+    fn params(&self) -> &HashMap<String, Weight> {
+        &self.params
+    }
+
+    fn params_mut(&mut self) -> &mut HashMap<String, Weight> {
+        &mut self.params
+    }
+
+    fn predict(&mut self, x: &Array2<f64>) -> Array2<f64> {
+        MnistNetworkBatch::predict(self, x)
+    }
+
+    fn loss(&mut self, x: &Array2<f64>, t: &Array2<f64>) -> f64 {
+        cross_entropy_error(&MnistNetworkBatch::predict(self, x), t)
+    }
+
+    fn gradient(&mut self, x: &Array2<f64>, t: &Array2<f64>) -> HashMap<String, Weight> {
+        sample_gradient(&self.params, x, t)
+    }
+}
+
 pub fn run() {
     let MnistDataset {
         x_train_2d,
         t_train,
         ..
     } = load_mnist((60_000, 0, 10_000), true, false);
-    let network = MnistNetworkBatch::new();
-    let batch_size = 100;
+    let network = MnistNetworkBatch::from(SampleWeights::load_sample());
+    let train_size = x_train_2d.nrows();
+    let mut loader = DataLoader::new(ArrayDataset::new(x_train_2d, t_train), 100)
+        .with_sampler(Sampler::Sequential);
+    let num_batches = loader.num_batches();
     let mut accuracy_cnt = 0_usize;
-    for i in 0..x_train_2d.nrows() / batch_size {
-        let i_batch = i * batch_size;
-        let x_batch = x_train_2d
-            .slice(s![i_batch..i_batch + batch_size, ..])
-            .into_owned();
+    let mut seen = 0_usize;
+    for (i, (x_batch, t_batch)) in loader.epoch().enumerate() {
         let y_batch = network.predict(&x_batch);
         let p = y_batch
             .rows()
             .into_iter()
             .map(|y| y.argmax().unwrap() as f64)
             .collect::<Array1<f64>>();
-        accuracy_cnt += (&p
-            - &t_train
-                .slice(s![i_batch..i_batch + batch_size, ..])
-                .into_owned()
-                .into_flat())
+        accuracy_cnt += (&p - &t_batch.into_flat())
             .mapv(|t| if t == 0. { 1 } else { 0 })
             .sum();
+        seen += p.len();
         println!(
-            "[{}/{}] Accuracy: {:.2}%",
+            "[{}/{num_batches}] Accuracy: {:.2}%",
             i + 1,
-            x_train_2d.nrows() / batch_size,
-            accuracy_cnt as f64 / (i_batch + batch_size) as f64 * 100.
+            accuracy_cnt as f64 / seen as f64 * 100.
         );
     }
     println!(
         "Accuracy: {:.2}%",
-        accuracy_cnt as f64 / x_train_2d.nrows() as f64 * 100.
+        accuracy_cnt as f64 / train_size as f64 * 100.
     );
 }
@@ -1,12 +1,18 @@
-use ndarray::{Array, Dimension};
+use ndarray::{Array, Axis, Dimension};
 use ndarray_stats::QuantileExt;
 
+/// Softmax over the last axis, so a (N, C) batch is normalized row by row.
 pub fn softmax<D>(x: &Array<f64, D>) -> Array<f64, D>
 where
     D: Dimension,
 {
-    let c = *x.max().unwrap(); // Optimize overflow
-    // println!("{c}");
-    let exp_x = x.mapv(|x| (x - c).exp());
-    exp_x.clone() / exp_x.sum()
+    let mut y = x.clone();
+    let last_axis = Axis(x.ndim() - 1);
+    for mut lane in y.lanes_mut(last_axis) {
+        let c = *lane.max().unwrap(); // Optimize overflow
+        lane.mapv_inplace(|x| (x - c).exp());
+        let sum = lane.sum();
+        lane /= sum;
+    }
+    y
 }
@@ -1,10 +1,38 @@
 use ndarray::{Array, Dimension};
 
+/// `t` must be one-hot (or smoothed) like `y`; (N, 1) class indices are rejected rather than
+/// broadcast.
 pub fn cross_entropy_error<D>(y: &Array<f64, D>, t: &Array<f64, D>) -> f64
 where
     D: Dimension,
 {
+    assert_eq!(
+        y.shape(),
+        t.shape(),
+        "labels must have the shape of the scores; one-hot encode class indices"
+    );
     let delta = 1e-7; // log(0) = -inf
     let batch_size = y.shape()[0] as f64;
     -(t * (y + delta).ln()).sum() / batch_size
 }
+
+#[cfg(test)]
+mod tests {
+    use ndarray::array;
+
+    use super::*;
+    use crate::ch05::layers::SoftmaxWithLoss;
+
+    #[test]
+    #[should_panic(expected = "one-hot encode class indices")]
+    fn rejects_index_labels() {
+        SoftmaxWithLoss::new().forward(&array![[1., 2., 3.], [3., 2., 1.]], &array![[2.], [0.]]);
+    }
+
+    #[test]
+    fn one_hot_labels() {
+        let y = array![[0.1, 0.9], [0.8, 0.2]];
+        let loss = cross_entropy_error(&y, &array![[0., 1.], [1., 0.]]);
+        assert!((loss - -(0.9_f64.ln() + 0.8_f64.ln()) / 2.).abs() < 1e-6);
+    }
+}
@@ -1,48 +1,90 @@
 use core::f64;
-use std::{cell::RefCell, collections::HashMap, rc::Rc};
+use std::{cell::RefCell, collections::HashMap, path::Path, rc::Rc};
 
-use ndarray::{Array, Array1, Array2, Axis, Ix1, Ix2};
-use ndarray_rand::{
-    RandomExt,
-    rand::{self, seq::index::sample},
-    rand_distr::StandardNormal,
-};
-use ndarray_stats::QuantileExt;
+use ndarray::{Array1, Array2, Array4, ArrayD, ArrayViewD, ArrayViewMutD, Ix1, Ix2};
 
 use crate::{
     ch03::{
-        mnist_dataset::{MnistDataset, load_mnist},
+        mnist_dataset::{ImageLayout, MnistDataset, MnistLoader, MnistVariant},
         sigmoid::sigmoid,
         softmax_function::softmax,
     },
     ch04::{cross_entropy_error::cross_entropy_error, gradient::numerical_gradient},
+    ch05::{
+        layers::{Affine, Layer, Sigmoid, SoftmaxWithLoss},
+        model::{Model, count_correct},
+    },
+    ch06::{
+        dropout::Dropout,
+        lr_scheduler::{PlateauMode, ReduceOnPlateau},
+        optimizer::Sgd,
+        trainer::{PrintProgress, Trainer},
+        weight_decay::WeightDecay,
+        weight_init::WeightInit,
+    },
+    dataset::data_loader::Sampler,
 };
 
 #[derive(Clone, Debug)]
 pub enum Weight {
     M1(Array1<f64>),
     M2(Array2<f64>),
+    /// Convolution filters (FN, C, FH, FW).
+    M4(Array4<f64>),
 }
 
 impl Weight {
     pub fn unwrap_m1(&self) -> Array1<f64> {
         match self {
             Self::M1(x) => x.clone(),
-            Self::M2(_) => panic!(),
+            _ => panic!(),
         }
     }
     pub fn unwrap_m2(&self) -> Array2<f64> {
         match self {
-            Self::M1(_) => panic!(),
             Self::M2(x) => x.clone(),
+            _ => panic!(),
+        }
+    }
+    pub fn unwrap_m4(&self) -> Array4<f64> {
+        match self {
+            Self::M4(x) => x.clone(),
+            _ => panic!(),
+        }
+    }
+    pub fn view(&self) -> ArrayViewD<'_, f64> {
+        match self {
+            Self::M1(x) => x.view().into_dyn(),
+            Self::M2(x) => x.view().into_dyn(),
+            Self::M4(x) => x.view().into_dyn(),
+        }
+    }
+    pub fn view_mut(&mut self) -> ArrayViewMutD<'_, f64> {
+        match self {
+            Self::M1(x) => x.view_mut().into_dyn(),
+            Self::M2(x) => x.view_mut().into_dyn(),
+            Self::M4(x) => x.view_mut().into_dyn(),
+        }
+    }
+    /// Pick the variant from the number of dimensions of `x`.
+    pub fn from_dyn(x: ArrayD<f64>) -> Self {
+        match x.ndim() {
+            1 => Self::M1(x.into_dimensionality().unwrap()),
+            2 => Self::M2(x.into_dimensionality().unwrap()),
+            4 => Self::M4(x.into_dimensionality().unwrap()),
+            n => panic!("no Weight variant for {n}-dimensional array"),
         }
     }
 }
 
+/// Affine -> Sigmoid -> [Dropout] -> Affine -> Softmax. A new network is in inference mode.
 #[derive(Clone)]
 pub struct TwoLayerNet {
     params: HashMap<String, Weight>,
     loss: Option<f64>, // cache loss to avoid recomputation
+    dropout: Option<Dropout>,
+    weight_decay: WeightDecay,
+    train_flg: bool,
 }
 
 impl TwoLayerNet {
@@ -50,11 +92,11 @@ impl TwoLayerNet {
         input_size: usize,
         hidden_size: usize,
         output_size: usize,
-        weight_init_std: f64,
+        weight_init: WeightInit,
     ) -> TwoLayerNet {
-        let w1 = weight_init_std * Array::random((input_size, hidden_size), StandardNormal);
+        let w1 = weight_init.dense(input_size, hidden_size);
         let b1 = Array1::zeros(hidden_size);
-        let w2 = weight_init_std * Array::random((hidden_size, output_size), StandardNormal);
+        let w2 = weight_init.dense(hidden_size, output_size);
         let b2 = Array1::zeros(output_size);
 
         let mut params = HashMap::new();
@@ -63,16 +105,43 @@ impl TwoLayerNet {
         params.insert("w2".to_owned(), Weight::M2(w2));
         params.insert("b2".to_owned(), Weight::M1(b2));
 
-        TwoLayerNet { params, loss: None }
+        TwoLayerNet {
+            params,
+            loss: None,
+            dropout: None,
+            weight_decay: WeightDecay::default(),
+            train_flg: false,
+        }
     }
-    pub fn predict(&self, x: &Array2<f64>) -> Array2<f64> {
+
+    /// Insert a `Dropout` after the hidden activation.
+    pub fn with_dropout(mut self, dropout_ratio: f64, seed: Option<u64>) -> Self {
+        self.dropout = Some(Dropout::new(Some(dropout_ratio), seed));
+        self
+    }
+
+    /// Add an L1/L2 penalty on `w1`/`w2` to the loss and to both gradients.
+    pub fn with_weight_decay(mut self, weight_decay: WeightDecay) -> Self {
+        self.weight_decay = weight_decay;
+        self
+    }
+
+    pub fn train_flg(&self) -> bool {
+        self.train_flg
+    }
+
+    pub fn predict(&mut self, x: &Array2<f64>) -> Array2<f64> {
         let w1 = self.params["w1"].unwrap_m2();
         let w2 = self.params["w2"].unwrap_m2();
         let b1 = self.params["b1"].unwrap_m1();
         let b2 = self.params["b2"].unwrap_m1();
 
         let a1 = x.dot(&w1) + b1;
-        let z1 = sigmoid(&a1);
+        let mut z1 = sigmoid(&a1);
+        if let Some(dropout) = self.dropout.as_mut() {
+            dropout.set_train_flg(self.train_flg);
+            z1 = dropout.forward(&z1);
+        }
         let a2 = z1.dot(&w2) + b2;
         let y = softmax(&a2);
 
@@ -82,7 +151,8 @@ impl TwoLayerNet {
     pub fn loss(&mut self, x: &Array2<f64>, t: &Array2<f64>) -> f64 {
         if self.loss.is_none() {
             let y = self.predict(x);
-            self.loss = Some(cross_entropy_error(&y, t));
+            let penalty = self.weight_decay.penalty(&self.params);
+            self.loss = Some(cross_entropy_error(&y, t) + penalty);
         }
         self.loss.unwrap()
     }
@@ -91,12 +161,9 @@ impl TwoLayerNet {
         self.loss = None;
     }
 
-    pub fn accuracy(&self, x: &Array2<f64>, t: &Array2<f64>) -> f64 {
-        let y = self.predict(&x);
-        let y = y.map_axis(Axis(0), |y| y.argmax().unwrap());
-        let t = t.map_axis(Axis(0), |t| t.argmax().unwrap());
-        let accuracy = (y - t).map(|&e| if e == 0 { 1. } else { 0. }).sum() / x.shape()[0] as f64;
-        accuracy
+    /// Ratio of rows whose argmax matches `t`, which is either one-hot or labels (N, 1).
+    pub fn accuracy(&mut self, x: &Array2<f64>, t: &Array2<f64>) -> f64 {
+        count_correct(&self.predict(x), t) as f64 / x.nrows() as f64
     }
 
     pub fn numerical_gradient(&self, x: &Array2<f64>, t: &Array2<f64>) -> HashMap<String, Weight> {
@@ -114,6 +181,7 @@ impl TwoLayerNet {
                 net.borrow_mut()
                     .params
                     .insert(w_key.clone(), Weight::M2(w.clone()));
+                net.borrow_mut().reset_loss();
                 let loss = net.borrow_mut().loss(x, t);
                 net.borrow_mut()
                     .params
@@ -126,6 +194,7 @@ impl TwoLayerNet {
                 net.borrow_mut()
                     .params
                     .insert(b_key.clone(), Weight::M1(b.clone()));
+                net.borrow_mut().reset_loss();
                 let loss = net.borrow_mut().loss(x, t);
                 net.borrow_mut()
                     .params
@@ -145,73 +214,158 @@ impl TwoLayerNet {
 
         grads
     }
+
+    /// Gradients by backpropagation. Returns the same keys as `numerical_gradient`.
+    /// Always differentiates the training-mode forward pass, whatever `train_flg` is.
+    pub fn gradient(&mut self, x: &Array2<f64>, t: &Array2<f64>) -> HashMap<String, Weight> {
+        let mut affine1 = Affine::new(self.params["w1"].unwrap_m2(), self.params["b1"].unwrap_m1());
+        let mut sigmoid1 = Sigmoid::new();
+        let mut affine2 = Affine::new(self.params["w2"].unwrap_m2(), self.params["b2"].unwrap_m1());
+        let mut last_layer = SoftmaxWithLoss::new();
+
+        // forward
+        let a1 = affine1.forward(x);
+        let mut z1 = sigmoid1.forward(&a1);
+        if let Some(dropout) = self.dropout.as_mut() {
+            dropout.set_train_flg(true);
+            z1 = dropout.forward(&z1);
+        }
+        let a2 = affine2.forward(&z1);
+        last_layer.forward(&a2, t);
+
+        // backward
+        let dout = last_layer.backward(1.);
+        let mut dout = affine2.backward(&dout);
+        if let Some(dropout) = self.dropout.as_mut() {
+            dout = dropout.backward(&dout);
+        }
+        let dout = sigmoid1.backward(&dout);
+        affine1.backward(&dout);
+
+        let mut grads = HashMap::new();
+        grads.insert("w1".to_owned(), Weight::M2(affine1.dw));
+        grads.insert("b1".to_owned(), Weight::M1(affine1.db));
+        grads.insert("w2".to_owned(), Weight::M2(affine2.dw));
+        grads.insert("b2".to_owned(), Weight::M1(affine2.db));
+        self.weight_decay.add_gradient(&self.params, &mut grads);
+
+        grads
+    }
+}
+
+impl Model for TwoLayerNet {
+    type Input = Array2<f64>;
+
+    fn params(&self) -> &HashMap<String, Weight> {
+        &self.params
+    }
+
+    fn params_mut(&mut self) -> &mut HashMap<String, Weight> {
+        &mut self.params
+    }
+
+    fn set_train_flg(&mut self, train_flg: bool) {
+        self.train_flg = train_flg;
+    }
+
+    fn predict(&mut self, x: &Array2<f64>) -> Array2<f64> {
+        TwoLayerNet::predict(self, x)
+    }
+
+    fn loss(&mut self, x: &Array2<f64>, t: &Array2<f64>) -> f64 {
+        self.reset_loss();
+        TwoLayerNet::loss(self, x, t)
+    }
+
+    fn accuracy(&mut self, x: &Array2<f64>, t: &Array2<f64>) -> f64 {
+        TwoLayerNet::accuracy(self, x, t)
+    }
+
+    fn gradient(&mut self, x: &Array2<f64>, t: &Array2<f64>) -> HashMap<String, Weight> {
+        TwoLayerNet::gradient(self, x, t)
+    }
+
+    fn numerical_gradient(&mut self, x: &Array2<f64>, t: &Array2<f64>) -> HashMap<String, Weight> {
+        TwoLayerNet::numerical_gradient(self, x, t)
+    }
 }
 
+/// Rewritten every epoch by `mini_batch` and read back by `mini_batch_resume`.
+pub const MINI_BATCH_CHECKPOINT_PATH: &str = "target/mini_batch.checkpoint";
+
 pub fn mini_batch() {
+    mini_batch_on(MnistVariant::Mnist, MINI_BATCH_CHECKPOINT_PATH, None);
+}
+
+/// Continue an interrupted `mini_batch` from its last checkpoint.
+pub fn mini_batch_resume() {
+    mini_batch_on(
+        MnistVariant::Mnist,
+        MINI_BATCH_CHECKPOINT_PATH,
+        Some(Path::new(MINI_BATCH_CHECKPOINT_PATH)),
+    );
+}
+
+/// `mini_batch` on any MNIST-shaped dataset. The output layer has one unit per class.
+/// Checkpoints go to `checkpoint_path`; training starts over unless `resume` names a
+/// checkpoint to continue from.
+pub fn mini_batch_on(
+    variant: MnistVariant,
+    checkpoint_path: impl AsRef<Path>,
+    resume: Option<&Path>,
+) {
     let MnistDataset {
+        class_names,
         x_train_2d,
         t_train,
         x_test_2d,
         t_test,
         ..
-    } = load_mnist((60_000, 0, 10_000), true, true);
+    } = MnistLoader::new()
+        .with_variant(variant)
+        .with_layout(ImageLayout::Flat)
+        .load()
+        .unwrap_or_else(|err| panic!("failed to load {variant:?}: {err}"));
 
-    let mut network = TwoLayerNet::new(784, 50, 10, 0.01);
+    let network = TwoLayerNet::new(
+        x_train_2d.ncols(),
+        50,
+        class_names.len(),
+        WeightInit::Std(0.01),
+    );
 
     // Hyperparameter
     let iters_num = 10000;
     let train_size = x_train_2d.shape()[0];
     let batch_size = 100;
     let learning_rate = 0.1;
+    let optimizer = Sgd::new(Some(learning_rate));
+    let scheduler = ReduceOnPlateau::new(learning_rate, PlateauMode::Max, None, Some(2), None);
 
-    let mut train_loss_list = Vec::new();
-    let mut train_acc_list = Vec::new();
-    let mut test_acc_list = Vec::new();
-
-    let mut iter_per_epoch = 1.max(train_size / batch_size);
-
-    for i in 1..=iters_num {
-        // mini batch
-        let batch_mask = sample(&mut rand::thread_rng(), train_size, batch_size).into_vec();
-        let x_batch = x_train_2d.select(Axis(0), &batch_mask);
-        let t_batch = t_train.select(Axis(0), &batch_mask);
-
-        // gradient
-        network.reset_loss();
-        let mut grad = network.numerical_gradient(&x_batch, &t_batch);
-
-        // update parameters
-        for key in ["w1", "b1", "w2", "b2"] {
-            match grad.get(key) {
-                Some(Weight::M1(b)) => {
-                    let mut param = network.params.get_mut(key).unwrap().unwrap_m1();
-                    param = param - learning_rate * b;
-                }
-                Some(Weight::M2(w)) => {
-                    let mut param = network.params.get_mut(key).unwrap().unwrap_m2();
-                    param = param - learning_rate * w;
-                }
-                _ => {
-                    panic!()
-                }
-            }
-        }
-
-        // loss
-        let loss = network.loss(&x_batch, &t_batch);
-        train_loss_list.push(loss);
+    let iter_per_epoch = 1.max(train_size / batch_size);
+    let epochs = iters_num / iter_per_epoch;
 
-        // accuracy per epoch
-        if i % iter_per_epoch == 0 {
-            let train_acc = network.accuracy(&x_train_2d, &t_train);
-            let test_acc = network.accuracy(&x_test_2d, &t_test);
-            train_acc_list.push(train_acc);
-            test_acc_list.push(test_acc);
-
-            println!("train acc: {:?} | test acc {:?}", train_acc, test_acc);
-        }
+    let mut trainer = Trainer::new(
+        network,
+        optimizer,
+        (x_train_2d, t_train),
+        (x_test_2d, t_test),
+        epochs,
+        batch_size,
+    )
+    .with_sampler(Sampler::Shuffle)
+    .with_drop_last(true)
+    .with_prefetch(4, 2)
+    .with_scheduler(scheduler)
+    .with_callback(PrintProgress {
+        loss_interval: Some(iter_per_epoch),
+    })
+    .with_checkpoint(checkpoint_path.as_ref(), iter_per_epoch);
+    if let Some(resume) = resume {
+        trainer
+            .resume(resume)
+            .unwrap_or_else(|err| panic!("failed to resume from {}: {err}", resume.display()));
+        println!("resumed from {}", resume.display());
     }
-
-    // print loss
-    dbg!(train_loss_list);
+    trainer.train();
 }
@@ -0,0 +1,562 @@
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    ops::{Add, Mul, Neg, Sub},
+};
+
+use ndarray::{Array, ArrayD, Axis, Ix2, IxDyn, array};
+use ndarray_rand::{RandomExt, rand_distr::StandardNormal};
+
+use crate::{
+    ch03::{sigmoid::sigmoid, softmax_function::softmax},
+    ch04::two_layer::TwoLayerNet,
+    ch05::model::Model,
+    ch06::weight_init::WeightInit,
+};
+
+pub type Tensor = ArrayD<f64>;
+
+/// Operation that produced a node. Operands are indices of earlier nodes on the same tape.
+#[derive(Clone, Debug)]
+enum Op {
+    Leaf,
+    Add(usize, usize),
+    Sub(usize, usize),
+    Mul(usize, usize),
+    Neg(usize),
+    AddScalar(usize),
+    MulScalar(usize, f64),
+    Dot(usize, usize),
+    BroadcastTo(usize),
+    Sum(usize),
+    SumAxis(usize, Axis),
+    Exp(usize),
+    Ln(usize),
+    Sigmoid(usize),
+    Softmax(usize),
+}
+
+#[derive(Clone, Debug)]
+struct Node {
+    value: Tensor,
+    op: Op,
+}
+
+/// Records every operation in creation order, so the backward pass is a single reverse sweep.
+#[derive(Debug, Default)]
+pub struct Tape {
+    nodes: RefCell<Vec<Node>>,
+}
+
+impl Tape {
+    pub fn new() -> Self {
+        Self {
+            nodes: RefCell::new(Vec::new()),
+        }
+    }
+
+    /// Put a leaf (input or parameter) on the tape.
+    pub fn var(&self, value: Tensor) -> Variable<'_> {
+        self.push(value, Op::Leaf)
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.borrow().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.borrow().is_empty()
+    }
+
+    fn push(&self, value: Tensor, op: Op) -> Variable<'_> {
+        let mut nodes = self.nodes.borrow_mut();
+        nodes.push(Node { value, op });
+        Variable {
+            tape: self,
+            index: nodes.len() - 1,
+        }
+    }
+
+    fn value(&self, index: usize) -> Tensor {
+        self.nodes.borrow()[index].value.clone()
+    }
+}
+
+/// Handle to a value on a `Tape`.
+#[derive(Clone, Copy, Debug)]
+pub struct Variable<'t> {
+    tape: &'t Tape,
+    index: usize,
+}
+
+impl<'t> Variable<'t> {
+    pub fn value(&self) -> Tensor {
+        self.tape.value(self.index)
+    }
+
+    pub fn shape(&self) -> Vec<usize> {
+        self.tape.nodes.borrow()[self.index].value.shape().to_vec()
+    }
+
+    fn unary(&self, value: Tensor, op: Op) -> Variable<'t> {
+        self.tape.push(value, op)
+    }
+
+    fn binary(&self, other: &Variable<'t>, value: Tensor, op: Op) -> Variable<'t> {
+        assert!(
+            std::ptr::eq(self.tape, other.tape),
+            "variables must live on the same tape"
+        );
+        self.tape.push(value, op)
+    }
+
+    pub fn add_scalar(&self, c: f64) -> Variable<'t> {
+        self.unary(self.value() + c, Op::AddScalar(self.index))
+    }
+
+    pub fn mul_scalar(&self, c: f64) -> Variable<'t> {
+        self.unary(self.value() * c, Op::MulScalar(self.index, c))
+    }
+
+    /// Matrix product of two 2-D variables.
+    pub fn dot(&self, other: &Variable<'t>) -> Variable<'t> {
+        let value = to_2d(&self.value()).dot(&to_2d(&other.value())).into_dyn();
+        self.binary(other, value, Op::Dot(self.index, other.index))
+    }
+
+    pub fn broadcast_to(&self, shape: &[usize]) -> Variable<'t> {
+        let value = self
+            .value()
+            .broadcast(IxDyn(shape))
+            .expect("shape is not broadcast compatible")
+            .to_owned();
+        self.unary(value, Op::BroadcastTo(self.index))
+    }
+
+    /// Sum of every element, as a 0-d variable.
+    pub fn sum(&self) -> Variable<'t> {
+        let value = ArrayD::from_elem(IxDyn(&[]), self.value().sum());
+        self.unary(value, Op::Sum(self.index))
+    }
+
+    pub fn sum_axis(&self, axis: Axis) -> Variable<'t> {
+        let value = self.value().sum_axis(axis);
+        self.unary(value, Op::SumAxis(self.index, axis))
+    }
+
+    pub fn exp(&self) -> Variable<'t> {
+        self.unary(self.value().exp(), Op::Exp(self.index))
+    }
+
+    pub fn ln(&self) -> Variable<'t> {
+        self.unary(self.value().ln(), Op::Ln(self.index))
+    }
+
+    pub fn sigmoid(&self) -> Variable<'t> {
+        self.unary(sigmoid(&self.value()), Op::Sigmoid(self.index))
+    }
+
+    /// Softmax over the last axis.
+    pub fn softmax(&self) -> Variable<'t> {
+        self.unary(softmax(&self.value()), Op::Softmax(self.index))
+    }
+
+    /// Reverse sweep over the tape from this (scalar) variable.
+    pub fn backward(&self) -> Gradients {
+        let nodes = self.tape.nodes.borrow();
+        assert_eq!(
+            nodes[self.index].value.len(),
+            1,
+            "backward must start from a scalar"
+        );
+
+        let mut grads: Vec<Option<Tensor>> = vec![None; nodes.len()];
+        grads[self.index] = Some(ArrayD::ones(nodes[self.index].value.raw_dim()));
+
+        for i in (0..=self.index).rev() {
+            let Some(gy) = grads[i].clone() else {
+                continue;
+            };
+            let node = &nodes[i];
+            let value_of = |j: usize| &nodes[j].value;
+            let mut accumulate = |j: usize, g: Tensor| {
+                grads[j] = Some(match grads[j].take() {
+                    Some(acc) => acc + g,
+                    None => g,
+                });
+            };
+
+            match node.op {
+                Op::Leaf => {}
+                Op::Add(a, b) => {
+                    accumulate(a, sum_to(&gy, value_of(a).shape()));
+                    accumulate(b, sum_to(&gy, value_of(b).shape()));
+                }
+                Op::Sub(a, b) => {
+                    accumulate(a, sum_to(&gy, value_of(a).shape()));
+                    accumulate(b, -sum_to(&gy, value_of(b).shape()));
+                }
+                Op::Mul(a, b) => {
+                    accumulate(a, sum_to(&(&gy * value_of(b)), value_of(a).shape()));
+                    accumulate(b, sum_to(&(&gy * value_of(a)), value_of(b).shape()));
+                }
+                Op::Neg(a) => accumulate(a, -gy),
+                Op::AddScalar(a) => accumulate(a, gy),
+                Op::MulScalar(a, c) => accumulate(a, gy * c),
+                Op::Dot(a, b) => {
+                    let gy = to_2d(&gy);
+                    accumulate(a, gy.dot(&to_2d(value_of(b)).t()).into_dyn());
+                    accumulate(b, to_2d(value_of(a)).t().dot(&gy).into_dyn());
+                }
+                Op::BroadcastTo(a) => accumulate(a, sum_to(&gy, value_of(a).shape())),
+                Op::Sum(a) => {
+                    let g = gy
+                        .broadcast(value_of(a).raw_dim())
+                        .expect("0-d gradient broadcasts to any shape")
+                        .to_owned();
+                    accumulate(a, g);
+                }
+                Op::SumAxis(a, axis) => {
+                    let g = gy
+                        .insert_axis(axis)
+                        .broadcast(value_of(a).raw_dim())
+                        .expect("summed axis broadcasts back")
+                        .to_owned();
+                    accumulate(a, g);
+                }
+                Op::Exp(a) => accumulate(a, gy * &node.value),
+                Op::Ln(a) => accumulate(a, gy / value_of(a)),
+                Op::Sigmoid(a) => accumulate(a, gy * node.value.mapv(|y| y * (1. - y))),
+                Op::Softmax(a) => {
+                    let y = &node.value;
+                    let gx = y * &gy;
+                    let last_axis = Axis(y.ndim() - 1);
+                    let sum_dx = gx.sum_axis(last_axis).insert_axis(last_axis);
+                    accumulate(a, &gx - &(y * &sum_dx));
+                }
+            }
+        }
+
+        Gradients { grads }
+    }
+}
+
+/// Element-wise addition with NumPy-style broadcasting.
+impl<'t> Add for Variable<'t> {
+    type Output = Variable<'t>;
+
+    fn add(self, rhs: Variable<'t>) -> Variable<'t> {
+        let value = &self.value() + &rhs.value();
+        self.binary(&rhs, value, Op::Add(self.index, rhs.index))
+    }
+}
+
+/// Element-wise subtraction with NumPy-style broadcasting.
+impl<'t> Sub for Variable<'t> {
+    type Output = Variable<'t>;
+
+    fn sub(self, rhs: Variable<'t>) -> Variable<'t> {
+        let value = &self.value() - &rhs.value();
+        self.binary(&rhs, value, Op::Sub(self.index, rhs.index))
+    }
+}
+
+/// Element-wise multiplication with NumPy-style broadcasting.
+impl<'t> Mul for Variable<'t> {
+    type Output = Variable<'t>;
+
+    fn mul(self, rhs: Variable<'t>) -> Variable<'t> {
+        let value = &self.value() * &rhs.value();
+        self.binary(&rhs, value, Op::Mul(self.index, rhs.index))
+    }
+}
+
+impl<'t> Neg for Variable<'t> {
+    type Output = Variable<'t>;
+
+    fn neg(self) -> Variable<'t> {
+        self.unary(-self.value(), Op::Neg(self.index))
+    }
+}
+
+/// Result of `Variable::backward`, indexed by the variables of the tape.
+#[derive(Clone, Debug)]
+pub struct Gradients {
+    grads: Vec<Option<Tensor>>,
+}
+
+impl Gradients {
+    /// Gradient of the loss with respect to `v`, or `None` if the loss does not depend on it.
+    pub fn get(&self, v: &Variable) -> Option<&Tensor> {
+        self.grads.get(v.index).and_then(|g| g.as_ref())
+    }
+}
+
+/// Cross entropy error on a tape, same formula as `ch04::cross_entropy_error`.
+pub fn cross_entropy_error<'t>(y: &Variable<'t>, t: &Variable<'t>) -> Variable<'t> {
+    assert_eq!(
+        y.shape(),
+        t.shape(),
+        "labels must have the shape of the scores; one-hot encode class indices"
+    );
+    let delta = 1e-7; // log(0) = -inf
+    let batch_size = y.shape()[0] as f64;
+    (*t * y.add_scalar(delta).ln())
+        .sum()
+        .mul_scalar(-1. / batch_size)
+}
+
+fn to_2d(x: &Tensor) -> ndarray::Array2<f64> {
+    x.clone()
+        .into_dimensionality::<Ix2>()
+        .expect("dot expects 2-D operands")
+}
+
+/// Reverse of broadcasting: sum `x` down to `shape`.
+fn sum_to(x: &Tensor, shape: &[usize]) -> Tensor {
+    let mut y = x.clone();
+    // leading axes added by broadcasting
+    while y.ndim() > shape.len() {
+        y = y.sum_axis(Axis(0));
+    }
+    // axes stretched from length 1
+    for (axis, &len) in shape.iter().enumerate() {
+        if len == 1 && y.shape()[axis] != 1 {
+            y = y.sum_axis(Axis(axis)).insert_axis(Axis(axis));
+        }
+    }
+    y
+}
+
+/// Backpropagate a `TwoLayerNet` on a tape and compare with its layer-based gradient.
+pub fn run() {
+    let mut network = TwoLayerNet::new(4, 5, 3, WeightInit::XavierNormal);
+    let x = Array::random((2, 4), StandardNormal);
+    let t = array![[0., 1., 0.], [1., 0., 0.]];
+
+    let tape = Tape::new();
+    let params = network
+        .params()
+        .iter()
+        .map(|(name, param)| (name.clone(), tape.var(param.view().to_owned())))
+        .collect::<HashMap<_, _>>();
+    let x_var = tape.var(x.clone().into_dyn());
+    let t_var = tape.var(t.clone().into_dyn());
+
+    let z1 = (x_var.dot(&params["w1"]) + params["b1"]).sigmoid();
+    let y = (z1.dot(&params["w2"]) + params["b2"]).softmax();
+    let loss = cross_entropy_error(&y, &t_var);
+    let grads = loss.backward();
+    println!(
+        "loss: {:.6}, tape length: {}",
+        loss.value().sum(),
+        tape.len()
+    );
+
+    let expected = network.gradient(&x, &t);
+    for key in ["w1", "b1", "w2", "b2"] {
+        let diff = (grads.get(&params[key]).unwrap() - &expected[key].view())
+            .abs()
+            .fold(0., |max: f64, &d| max.max(d));
+        println!("{key}: max |tape - backprop| = {diff:e}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ndarray::{IxDyn, indices_of};
+    use ndarray_rand::{
+        rand::SeedableRng,
+        rand_distr::{Distribution, Uniform},
+    };
+    use rand_chacha::ChaCha8Rng;
+
+    use super::*;
+
+    fn random(shape: &[usize], dist: impl Distribution<f64>, rng: &mut ChaCha8Rng) -> Tensor {
+        Array::random_using(IxDyn(shape), dist, rng)
+    }
+
+    /// Compare the tape gradient of `f` for every input with central differences. `f` may return
+    /// any shape; its output is reduced by a weighted sum with fixed random weights.
+    fn check_gradient<F>(inputs: &[Tensor], f: F)
+    where
+        F: for<'t> Fn(&[Variable<'t>]) -> Variable<'t>,
+    {
+        let y_shape = {
+            let tape = Tape::new();
+            let vars = inputs
+                .iter()
+                .map(|x| tape.var(x.clone()))
+                .collect::<Vec<_>>();
+            f(&vars).shape()
+        };
+        let weights = random(&y_shape, StandardNormal, &mut ChaCha8Rng::seed_from_u64(0));
+        // loss and the gradient of every input
+        let loss = |inputs: &[Tensor]| {
+            let tape = Tape::new();
+            let vars = inputs
+                .iter()
+                .map(|x| tape.var(x.clone()))
+                .collect::<Vec<_>>();
+            let loss = (f(&vars) * tape.var(weights.clone())).sum();
+            let grads = loss.backward();
+            let grads = vars
+                .iter()
+                .map(|v| grads.get(v).cloned())
+                .collect::<Vec<_>>();
+            (loss.value().sum(), grads)
+        };
+
+        let (_, grads) = loss(inputs);
+        let h = 1e-5;
+        for (k, input) in inputs.iter().enumerate() {
+            let grad = grads[k].as_ref().expect("every input reaches the output");
+            assert_eq!(grad.shape(), input.shape(), "shape of gradient {k}");
+            for idx in indices_of(input) {
+                let mut shifted = inputs.to_vec();
+                shifted[k][&idx] = input[&idx] + h;
+                let (fxh1, _) = loss(&shifted);
+                shifted[k][&idx] = input[&idx] - h;
+                let (fxh2, _) = loss(&shifted);
+
+                let numerical = (fxh1 - fxh2) / (2. * h);
+                let diff = (grad[&idx] - numerical).abs();
+                assert!(
+                    diff < 1e-6,
+                    "input {k} at {idx:?}: {} vs {numerical}",
+                    grad[&idx]
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn add_with_broadcasting() {
+        let mut rng = ChaCha8Rng::seed_from_u64(1);
+        let inputs = [
+            random(&[3, 4], StandardNormal, &mut rng),
+            random(&[4], StandardNormal, &mut rng),
+        ];
+        check_gradient(&inputs, |v| v[0] + v[1]);
+    }
+
+    #[test]
+    fn sub_with_broadcasting() {
+        let mut rng = ChaCha8Rng::seed_from_u64(2);
+        let inputs = [
+            random(&[3, 4], StandardNormal, &mut rng),
+            random(&[3, 1], StandardNormal, &mut rng),
+        ];
+        check_gradient(&inputs, |v| v[0] - v[1]);
+    }
+
+    #[test]
+    fn mul_with_broadcasting() {
+        let mut rng = ChaCha8Rng::seed_from_u64(3);
+        let inputs = [
+            random(&[1, 4], StandardNormal, &mut rng),
+            random(&[3, 1], StandardNormal, &mut rng),
+        ];
+        check_gradient(&inputs, |v| v[0] * v[1]);
+    }
+
+    #[test]
+    fn neg_and_scalars() {
+        let mut rng = ChaCha8Rng::seed_from_u64(4);
+        let inputs = [random(&[3, 4], StandardNormal, &mut rng)];
+        check_gradient(&inputs, |v| -v[0].add_scalar(2.).mul_scalar(-3.));
+    }
+
+    #[test]
+    fn dot() {
+        let mut rng = ChaCha8Rng::seed_from_u64(5);
+        let inputs = [
+            random(&[3, 4], StandardNormal, &mut rng),
+            random(&[4, 2], StandardNormal, &mut rng),
+        ];
+        check_gradient(&inputs, |v| v[0].dot(&v[1]));
+    }
+
+    #[test]
+    fn broadcast_to() {
+        let mut rng = ChaCha8Rng::seed_from_u64(6);
+        let inputs = [random(&[1, 4], StandardNormal, &mut rng)];
+        check_gradient(&inputs, |v| v[0].broadcast_to(&[2, 3, 4]));
+    }
+
+    #[test]
+    fn sum() {
+        let mut rng = ChaCha8Rng::seed_from_u64(7);
+        let inputs = [random(&[3, 4], StandardNormal, &mut rng)];
+        check_gradient(&inputs, |v| v[0].sum());
+    }
+
+    #[test]
+    fn sum_axis() {
+        let mut rng = ChaCha8Rng::seed_from_u64(8);
+        let inputs = [random(&[2, 3, 4], StandardNormal, &mut rng)];
+        check_gradient(&inputs, |v| v[0].sum_axis(Axis(1)));
+    }
+
+    #[test]
+    fn exp() {
+        let mut rng = ChaCha8Rng::seed_from_u64(9);
+        let inputs = [random(&[3, 4], StandardNormal, &mut rng)];
+        check_gradient(&inputs, |v| v[0].exp());
+    }
+
+    #[test]
+    fn ln() {
+        let mut rng = ChaCha8Rng::seed_from_u64(10);
+        let inputs = [random(&[3, 4], Uniform::new(0.5, 2.), &mut rng)];
+        check_gradient(&inputs, |v| v[0].ln());
+    }
+
+    #[test]
+    fn sigmoid() {
+        let mut rng = ChaCha8Rng::seed_from_u64(11);
+        let inputs = [random(&[3, 4], StandardNormal, &mut rng)];
+        check_gradient(&inputs, |v| v[0].sigmoid());
+    }
+
+    #[test]
+    fn softmax() {
+        let mut rng = ChaCha8Rng::seed_from_u64(12);
+        let inputs = [random(&[3, 4], StandardNormal, &mut rng)];
+        check_gradient(&inputs, |v| v[0].softmax());
+    }
+
+    #[test]
+    fn reused_variable_accumulates() {
+        let mut rng = ChaCha8Rng::seed_from_u64(13);
+        let inputs = [random(&[3, 4], StandardNormal, &mut rng)];
+        check_gradient(&inputs, |v| v[0] * v[0].exp() + v[0]);
+    }
+
+    #[test]
+    fn two_layer_net_loss() {
+        let mut rng = ChaCha8Rng::seed_from_u64(14);
+        let inputs = [
+            random(&[2, 4], StandardNormal, &mut rng),
+            random(&[4, 5], StandardNormal, &mut rng),
+            random(&[5], StandardNormal, &mut rng),
+            random(&[5, 3], StandardNormal, &mut rng),
+            random(&[3], StandardNormal, &mut rng),
+        ];
+        check_gradient(&inputs, |v| {
+            let t = v[0].tape.var(array![[0., 1., 0.], [1., 0., 0.]].into_dyn());
+            let y = ((v[0].dot(&v[1]) + v[2]).sigmoid().dot(&v[3]) + v[4]).softmax();
+            cross_entropy_error(&y, &t)
+        });
+    }
+
+    #[test]
+    fn sum_to_reverses_broadcasting() {
+        let x = Tensor::ones(IxDyn(&[2, 3, 4]));
+        assert_eq!(sum_to(&x, &[3, 4]), Tensor::from_elem(IxDyn(&[3, 4]), 2.));
+        assert_eq!(sum_to(&x, &[3, 1]), Tensor::from_elem(IxDyn(&[3, 1]), 8.));
+        assert_eq!(sum_to(&x, &[1, 4]), Tensor::from_elem(IxDyn(&[1, 4]), 6.));
+        assert_eq!(sum_to(&x, &[2, 3, 4]), x);
+    }
+}
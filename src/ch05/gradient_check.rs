@@ -0,0 +1,463 @@
+use std::{collections::HashMap, fmt};
+
+use ndarray::{Array, Array2, Axis, Dimension, Ix2, Zip};
+use ndarray_rand::{
+    RandomExt,
+    rand_distr::{StandardNormal, Uniform},
+};
+
+use crate::{
+    ch03::softmax_function::softmax,
+    ch04::two_layer::{TwoLayerNet, Weight},
+    ch05::{
+        layers::{Affine, Layer, Relu, Sigmoid, SoftmaxWithLoss},
+        model::Model,
+    },
+    ch06::weight_init::WeightInit,
+};
+
+/// Largest disagreement between analytic and numerical gradient for one parameter.
+#[derive(Clone, Debug)]
+pub struct ParamError {
+    pub name: String,
+    pub max_abs_error: f64,
+    pub max_rel_error: f64,
+    pub passed: bool,
+}
+
+#[derive(Clone, Debug)]
+pub struct GradientCheckReport {
+    pub threshold: f64,
+    pub params: Vec<ParamError>,
+}
+
+impl GradientCheckReport {
+    pub fn passed(&self) -> bool {
+        self.params.iter().all(|p| p.passed)
+    }
+}
+
+impl fmt::Display for GradientCheckReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for p in &self.params {
+            writeln!(
+                f,
+                "{}: max abs {:e} | max rel {:e} | {}",
+                p.name,
+                p.max_abs_error,
+                p.max_rel_error,
+                if p.passed { "ok" } else { "FAIL" }
+            )?;
+        }
+        write!(
+            f,
+            "{} (threshold {:e})",
+            if self.passed() { "passed" } else { "failed" },
+            self.threshold
+        )
+    }
+}
+
+/// Compare two gradient maps element by element.
+/// An element passes when `|analytic - numerical| <= threshold * (1 + |numerical|)`,
+/// i.e. absolute error for small gradients and relative error for large ones.
+pub fn compare_gradients(
+    analytic: &HashMap<String, Weight>,
+    numerical: &HashMap<String, Weight>,
+    threshold: f64,
+) -> GradientCheckReport {
+    let mut names = numerical.keys().cloned().collect::<Vec<_>>();
+    names.sort();
+
+    let params = names
+        .into_iter()
+        .map(|name| {
+            let n = numerical[&name].view();
+            let a = analytic
+                .get(&name)
+                .unwrap_or_else(|| panic!("analytic gradient is missing `{name}`"))
+                .view();
+            assert_eq!(a.shape(), n.shape(), "shape mismatch for `{name}`");
+
+            let mut max_abs_error = 0_f64;
+            let mut max_rel_error = 0_f64;
+            let mut passed = true;
+            Zip::from(&a).and(&n).for_each(|&a, &n| {
+                let abs = (a - n).abs();
+                let scale = a.abs().max(n.abs());
+                let rel = if scale == 0. { 0. } else { abs / scale };
+                max_abs_error = max_abs_error.max(abs);
+                max_rel_error = max_rel_error.max(rel);
+                passed &= abs <= threshold * (1. + n.abs());
+            });
+
+            ParamError {
+                name,
+                max_abs_error,
+                max_rel_error,
+                passed,
+            }
+        })
+        .collect();
+
+    GradientCheckReport { threshold, params }
+}
+
+/// Run both gradient paths of `model` on the same batch and compare them.
+/// Both are taken in training mode. Dropout draws a new mask on every forward pass,
+/// so models with dropout only check out with a ratio of 0.
+pub fn gradient_check<M>(
+    model: &mut M,
+    x: &M::Input,
+    t: &Array2<f64>,
+    threshold: f64,
+) -> GradientCheckReport
+where
+    M: Model,
+{
+    model.train();
+    let numerical = model.numerical_gradient(x, t);
+    let analytic = model.gradient(x, t);
+    compare_gradients(&analytic, &numerical, threshold)
+}
+
+/// Wraps a single layer in the scalar loss `sum(layer(x) * t)`, so `t` plays the role of the
+/// upstream gradient. The input is stored as parameter `"x"` next to the layer's own parameters,
+/// which lets `gradient_check` verify dx as well as dw/db.
+/// Outputs of higher rank are flattened to (N, -1); `t` is given in that flattened shape.
+pub struct LayerProbe<L, D = Ix2> {
+    layer: L,
+    params: HashMap<String, Weight>,
+    out_shape: Option<D>,
+}
+
+impl<L, D> LayerProbe<L, D>
+where
+    L: Layer<D>,
+    D: Dimension,
+{
+    pub fn new(layer: L, x: Array<f64, D>) -> Self {
+        let mut params = layer.params();
+        params.insert("x".to_owned(), Weight::from_dyn(x.into_dyn()));
+        Self {
+            layer,
+            params,
+            out_shape: None,
+        }
+    }
+
+    fn forward(&mut self) -> Array2<f64> {
+        self.layer.set_params(&self.params);
+        let x = self.params["x"]
+            .view()
+            .to_owned()
+            .into_dimensionality::<D>()
+            .unwrap();
+        let out = self.layer.forward(&x);
+        let (n, len) = (out.len_of(Axis(0)), out.len());
+        self.out_shape = Some(out.raw_dim());
+        out.to_shape((n, len / n)).unwrap().into_owned()
+    }
+}
+
+impl<L, D> Model for LayerProbe<L, D>
+where
+    L: Layer<D>,
+    D: Dimension,
+{
+    type Input = ();
+
+    fn params(&self) -> &HashMap<String, Weight> {
+        &self.params
+    }
+
+    fn params_mut(&mut self) -> &mut HashMap<String, Weight> {
+        &mut self.params
+    }
+
+    fn predict(&mut self, _x: &()) -> Array2<f64> {
+        self.forward()
+    }
+
+    fn set_train_flg(&mut self, train_flg: bool) {
+        self.layer.set_train_flg(train_flg);
+    }
+
+    fn loss(&mut self, _x: &(), t: &Array2<f64>) -> f64 {
+        (self.forward() * t).sum()
+    }
+
+    fn gradient(&mut self, _x: &(), t: &Array2<f64>) -> HashMap<String, Weight> {
+        self.forward();
+        let dout = t
+            .to_shape(self.out_shape.clone().unwrap())
+            .expect("t does not match the flattened output")
+            .into_owned();
+        let dx = self.layer.backward(&dout);
+        let mut grads = self.layer.grads();
+        grads.insert("x".to_owned(), Weight::from_dyn(dx.into_dyn()));
+        grads
+    }
+}
+
+/// `SoftmaxWithLoss` is already a scalar loss, so only its input needs to be a parameter.
+pub struct SoftmaxWithLossProbe {
+    layer: SoftmaxWithLoss,
+    params: HashMap<String, Weight>,
+}
+
+impl SoftmaxWithLossProbe {
+    pub fn new(x: Array2<f64>) -> Self {
+        let mut params = HashMap::new();
+        params.insert("x".to_owned(), Weight::M2(x));
+        Self {
+            layer: SoftmaxWithLoss::new(),
+            params,
+        }
+    }
+}
+
+impl Model for SoftmaxWithLossProbe {
+    type Input = ();
+
+    fn params(&self) -> &HashMap<String, Weight> {
+        &self.params
+    }
+
+    fn params_mut(&mut self) -> &mut HashMap<String, Weight> {
+        &mut self.params
+    }
+
+    fn predict(&mut self, _x: &()) -> Array2<f64> {
+        softmax(&self.params["x"].unwrap_m2())
+    }
+
+    fn loss(&mut self, _x: &(), t: &Array2<f64>) -> f64 {
+        let x = self.params["x"].unwrap_m2();
+        self.layer.forward(&x, t)
+    }
+
+    fn gradient(&mut self, x: &(), t: &Array2<f64>) -> HashMap<String, Weight> {
+        self.loss(x, t);
+        let mut grads = HashMap::new();
+        grads.insert("x".to_owned(), Weight::M2(self.layer.backward(1.)));
+        grads
+    }
+}
+
+pub fn run() {
+    let threshold = 1e-5;
+    let x = Array::random((3, 4), StandardNormal);
+    let dout = Array::random((3, 4), StandardNormal);
+
+    let mut relu = LayerProbe::new(Relu::new(), x.clone());
+    println!("Relu\n{}", gradient_check(&mut relu, &(), &dout, threshold));
+
+    let mut sigmoid = LayerProbe::new(Sigmoid::new(), x.clone());
+    println!(
+        "Sigmoid\n{}",
+        gradient_check(&mut sigmoid, &(), &dout, threshold)
+    );
+
+    let affine = Affine::new(
+        Array::random((4, 5), StandardNormal),
+        Array::random(5, StandardNormal),
+    );
+    let mut affine = LayerProbe::new(affine, x.clone());
+    let dout = Array::random((3, 5), StandardNormal);
+    println!(
+        "Affine\n{}",
+        gradient_check(&mut affine, &(), &dout, threshold)
+    );
+
+    let mut t = Array2::zeros((3, 4));
+    t[[0, 1]] = 1.;
+    t[[1, 3]] = 1.;
+    t[[2, 0]] = 1.;
+    let mut softmax_with_loss = SoftmaxWithLossProbe::new(x);
+    println!(
+        "SoftmaxWithLoss\n{}",
+        gradient_check(&mut softmax_with_loss, &(), &t, threshold)
+    );
+
+    let mut network = TwoLayerNet::new(784, 50, 10, WeightInit::Std(0.01));
+    let x_batch = Array::random((3, 784), Uniform::new(0., 1.));
+    let mut t_batch = Array2::zeros((3, 10));
+    t_batch[[0, 7]] = 1.;
+    t_batch[[1, 2]] = 1.;
+    t_batch[[2, 1]] = 1.;
+    println!(
+        "TwoLayerNet\n{}",
+        gradient_check(&mut network, &x_batch, &t_batch, threshold)
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use ndarray::{Array1, Array4, Ix4, ShapeBuilder};
+    use ndarray_rand::rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+
+    use super::*;
+    use crate::{
+        ch06::{batch_normalization::BatchNormalization, dropout::Dropout},
+        ch07::{
+            convolution::Convolution,
+            flatten::Flatten,
+            pooling::{AvgPooling, MaxPooling},
+        },
+    };
+
+    const THRESHOLD: f64 = 1e-5;
+
+    fn random<Sh: ShapeBuilder>(shape: Sh, rng: &mut ChaCha8Rng) -> Array<f64, Sh::Dim> {
+        Array::random_using(shape, StandardNormal, rng)
+    }
+
+    fn one_hot(labels: &[usize], classes: usize) -> Array2<f64> {
+        Array2::from_shape_fn((labels.len(), classes), |(i, j)| {
+            if labels[i] == j { 1. } else { 0. }
+        })
+    }
+
+    fn assert_passes(report: GradientCheckReport) {
+        assert!(report.passed(), "{report}");
+    }
+
+    #[test]
+    fn relu() {
+        let mut rng = ChaCha8Rng::seed_from_u64(0);
+        let mut probe = LayerProbe::new(Relu::new(), random((3, 4), &mut rng));
+        let dout = random((3, 4), &mut rng);
+        assert_passes(gradient_check(&mut probe, &(), &dout, THRESHOLD));
+    }
+
+    #[test]
+    fn sigmoid() {
+        let mut rng = ChaCha8Rng::seed_from_u64(1);
+        let mut probe = LayerProbe::new(Sigmoid::new(), random((3, 4), &mut rng));
+        let dout = random((3, 4), &mut rng);
+        assert_passes(gradient_check(&mut probe, &(), &dout, THRESHOLD));
+    }
+
+    #[test]
+    fn affine() {
+        let mut rng = ChaCha8Rng::seed_from_u64(2);
+        let affine = Affine::new(random((4, 5), &mut rng), random(5, &mut rng));
+        let mut probe = LayerProbe::new(affine, random((3, 4), &mut rng));
+        let dout = random((3, 5), &mut rng);
+        assert_passes(gradient_check(&mut probe, &(), &dout, THRESHOLD));
+    }
+
+    #[test]
+    fn softmax_with_loss() {
+        let mut rng = ChaCha8Rng::seed_from_u64(3);
+        let mut probe = SoftmaxWithLossProbe::new(random((3, 4), &mut rng));
+        let t = one_hot(&[1, 3, 0], 4);
+        assert_passes(gradient_check(&mut probe, &(), &t, THRESHOLD));
+    }
+
+    #[test]
+    fn batch_normalization() {
+        let mut rng = ChaCha8Rng::seed_from_u64(4);
+        let layer = BatchNormalization::new(random(4, &mut rng), random(4, &mut rng), None);
+        let mut probe = LayerProbe::new(layer, random((5, 4), &mut rng));
+        let dout = random((5, 4), &mut rng);
+        assert_passes(gradient_check(&mut probe, &(), &dout, THRESHOLD));
+    }
+
+    /// Dropout that draws the same mask on every forward pass.
+    struct FixedMaskDropout(Dropout);
+
+    impl Layer for FixedMaskDropout {
+        fn forward(&mut self, x: &Array2<f64>) -> Array2<f64> {
+            self.0 = Dropout::new(Some(0.5), Some(7));
+            self.0.forward(x)
+        }
+
+        fn backward(&mut self, dout: &Array2<f64>) -> Array2<f64> {
+            self.0.backward(dout)
+        }
+    }
+
+    #[test]
+    fn dropout() {
+        let mut rng = ChaCha8Rng::seed_from_u64(5);
+        let layer = FixedMaskDropout(Dropout::new(Some(0.5), Some(7)));
+        let mut probe = LayerProbe::new(layer, random((3, 4), &mut rng));
+        let dout = random((3, 4), &mut rng);
+        assert_passes(gradient_check(&mut probe, &(), &dout, THRESHOLD));
+    }
+
+    #[test]
+    fn convolution() {
+        let mut rng = ChaCha8Rng::seed_from_u64(6);
+        let layer = Convolution::new(
+            random((3, 2, 3, 3), &mut rng),
+            random(3, &mut rng),
+            Some(1),
+            Some(1),
+        );
+        let mut probe = LayerProbe::<_, Ix4>::new(layer, random((2, 2, 5, 5), &mut rng));
+        // output (2, 3, 5, 5) flattened to (2, 75)
+        let dout = random((2, 75), &mut rng);
+        assert_passes(gradient_check(&mut probe, &(), &dout, THRESHOLD));
+    }
+
+    #[test]
+    fn pooling() {
+        let mut rng = ChaCha8Rng::seed_from_u64(7);
+        let x: Array4<f64> = random((2, 3, 4, 4), &mut rng);
+        // output (2, 3, 2, 2) flattened to (2, 12)
+        let dout = random((2, 12), &mut rng);
+
+        let mut max = LayerProbe::new(MaxPooling::new(2, 2, Some(2), None), x.clone());
+        assert_passes(gradient_check(&mut max, &(), &dout, THRESHOLD));
+        let mut avg = LayerProbe::new(AvgPooling::new(2, 2, Some(2), None), x);
+        assert_passes(gradient_check(&mut avg, &(), &dout, THRESHOLD));
+    }
+
+    /// Flatten with its (N, C * H * W) output padded to (N, C * H * W, 1, 1), so it fits
+    /// `LayerProbe`'s rank-preserving `Layer`.
+    struct Flattened(Flatten);
+
+    impl Layer<Ix4> for Flattened {
+        fn forward(&mut self, x: &Array4<f64>) -> Array4<f64> {
+            let out = self.0.forward(x);
+            let (n, len) = out.dim();
+            out.into_shape_with_order((n, len, 1, 1)).unwrap()
+        }
+
+        fn backward(&mut self, dout: &Array4<f64>) -> Array4<f64> {
+            let (n, len, _, _) = dout.dim();
+            self.0
+                .backward(&dout.to_shape((n, len)).unwrap().into_owned())
+        }
+    }
+
+    #[test]
+    fn flatten() {
+        let mut rng = ChaCha8Rng::seed_from_u64(9);
+        let mut probe = LayerProbe::new(Flattened(Flatten::new()), random((2, 3, 2, 4), &mut rng));
+        let dout = random((2, 24), &mut rng);
+        assert_passes(gradient_check(&mut probe, &(), &dout, THRESHOLD));
+    }
+
+    #[test]
+    fn two_layer_net() {
+        let mut rng = ChaCha8Rng::seed_from_u64(8);
+        let mut network = TwoLayerNet::new(6, 5, 3, WeightInit::Std(0.5));
+        let x = random((4, 6), &mut rng);
+        let t = one_hot(&[0, 2, 1, 2], 3);
+        assert_passes(gradient_check(&mut network, &x, &t, THRESHOLD));
+    }
+
+    #[test]
+    fn compare_gradients_flags_a_wrong_gradient() {
+        let numerical = HashMap::from([("w".to_owned(), Weight::M1(Array1::from(vec![1., 2.])))]);
+        let analytic = HashMap::from([("w".to_owned(), Weight::M1(Array1::from(vec![1., 2.1])))]);
+        let report = compare_gradients(&analytic, &numerical, THRESHOLD);
+        assert!(!report.passed());
+        assert!((report.params[0].max_abs_error - 0.1).abs() < 1e-12);
+    }
+}
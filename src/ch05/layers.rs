@@ -0,0 +1,216 @@
+use std::collections::HashMap;
+
+use ndarray::{Array, Array1, Array2, Axis, Dimension, Ix2, Zip};
+
+use crate::{
+    ch03::{relu::relu, sigmoid::sigmoid, softmax_function::softmax},
+    ch04::{cross_entropy_error::cross_entropy_error, two_layer::Weight},
+};
+
+/// Common interface of the layers that map an array to an array of the same rank.
+pub trait Layer<D = Ix2>
+where
+    D: Dimension,
+{
+    fn forward(&mut self, x: &Array<f64, D>) -> Array<f64, D>;
+
+    fn backward(&mut self, dout: &Array<f64, D>) -> Array<f64, D>;
+
+    /// Learnable parameters, keyed by their local name (`"w"`, `"b"`, ...).
+    fn params(&self) -> HashMap<String, Weight> {
+        HashMap::new()
+    }
+
+    /// Overwrite the parameters whose keys appear in `params`.
+    fn set_params(&mut self, _params: &HashMap<String, Weight>) {}
+
+    /// Gradients of the last backward pass, keyed like `params`.
+    fn grads(&self) -> HashMap<String, Weight> {
+        HashMap::new()
+    }
+
+    /// Switch between training and inference behaviour. Only layers that differ care.
+    fn set_train_flg(&mut self, _train_flg: bool) {}
+}
+
+/// ReLU layer. `mask` remembers where the input was <= 0 so backward can block those elements.
+#[derive(Clone, Debug, Default)]
+pub struct Relu<D = Ix2>
+where
+    D: Dimension,
+{
+    mask: Option<Array<bool, D>>,
+}
+
+impl<D> Relu<D>
+where
+    D: Dimension,
+{
+    pub fn new() -> Self {
+        Self { mask: None }
+    }
+}
+
+impl<D> Layer<D> for Relu<D>
+where
+    D: Dimension,
+{
+    fn forward(&mut self, x: &Array<f64, D>) -> Array<f64, D> {
+        self.mask = Some(x.mapv(|x| x <= 0.));
+        relu(x)
+    }
+
+    fn backward(&mut self, dout: &Array<f64, D>) -> Array<f64, D> {
+        let mask = self
+            .mask
+            .as_ref()
+            .expect("Relu::backward called before forward");
+        let mut dx = dout.clone();
+        Zip::from(&mut dx).and(mask).for_each(|dx, &m| {
+            if m {
+                *dx = 0.;
+            }
+        });
+        dx
+    }
+}
+
+/// Sigmoid layer. The forward output is enough to compute the local gradient `y(1 - y)`.
+#[derive(Clone, Debug, Default)]
+pub struct Sigmoid<D = Ix2>
+where
+    D: Dimension,
+{
+    out: Option<Array<f64, D>>,
+}
+
+impl<D> Sigmoid<D>
+where
+    D: Dimension,
+{
+    pub fn new() -> Self {
+        Self { out: None }
+    }
+}
+
+impl<D> Layer<D> for Sigmoid<D>
+where
+    D: Dimension,
+{
+    fn forward(&mut self, x: &Array<f64, D>) -> Array<f64, D> {
+        let out = sigmoid(x);
+        self.out = Some(out.clone());
+        out
+    }
+
+    fn backward(&mut self, dout: &Array<f64, D>) -> Array<f64, D> {
+        let out = self
+            .out
+            .as_ref()
+            .expect("Sigmoid::backward called before forward");
+        dout * &out.mapv(|y| (1. - y) * y)
+    }
+}
+
+/// Fully connected layer `y = x·W + b`. Gradients of the last backward pass are kept in `dw`/`db`.
+#[derive(Clone, Debug)]
+pub struct Affine {
+    pub w: Array2<f64>,
+    pub b: Array1<f64>,
+    x: Option<Array2<f64>>,
+    pub dw: Array2<f64>,
+    pub db: Array1<f64>,
+}
+
+impl Affine {
+    pub fn new(w: Array2<f64>, b: Array1<f64>) -> Self {
+        let dw = Array2::zeros(w.raw_dim());
+        let db = Array1::zeros(b.raw_dim());
+        Self {
+            w,
+            b,
+            x: None,
+            dw,
+            db,
+        }
+    }
+}
+
+impl Layer for Affine {
+    fn forward(&mut self, x: &Array2<f64>) -> Array2<f64> {
+        self.x = Some(x.clone());
+        x.dot(&self.w) + &self.b
+    }
+
+    fn backward(&mut self, dout: &Array2<f64>) -> Array2<f64> {
+        let x = self
+            .x
+            .as_ref()
+            .expect("Affine::backward called before forward");
+        let dx = dout.dot(&self.w.t());
+        self.dw = x.t().dot(dout);
+        self.db = dout.sum_axis(Axis(0));
+        dx
+    }
+
+    fn params(&self) -> HashMap<String, Weight> {
+        HashMap::from([
+            ("w".to_owned(), Weight::M2(self.w.clone())),
+            ("b".to_owned(), Weight::M1(self.b.clone())),
+        ])
+    }
+
+    fn set_params(&mut self, params: &HashMap<String, Weight>) {
+        if let Some(w) = params.get("w") {
+            self.w = w.unwrap_m2();
+        }
+        if let Some(b) = params.get("b") {
+            self.b = b.unwrap_m1();
+        }
+    }
+
+    fn grads(&self) -> HashMap<String, Weight> {
+        HashMap::from([
+            ("w".to_owned(), Weight::M2(self.dw.clone())),
+            ("b".to_owned(), Weight::M1(self.db.clone())),
+        ])
+    }
+}
+
+/// Softmax followed by cross entropy error, fused so the backward pass is simply `(y - t) / N`.
+/// `t` must be one-hot encoded; `forward` panics on (N, 1) class indices.
+#[derive(Clone, Debug, Default)]
+pub struct SoftmaxWithLoss {
+    pub loss: Option<f64>,
+    y: Option<Array2<f64>>,
+    t: Option<Array2<f64>>,
+}
+
+impl SoftmaxWithLoss {
+    pub fn new() -> Self {
+        Self {
+            loss: None,
+            y: None,
+            t: None,
+        }
+    }
+
+    pub fn forward(&mut self, x: &Array2<f64>, t: &Array2<f64>) -> f64 {
+        let y = softmax(x);
+        let loss = cross_entropy_error(&y, t);
+        self.y = Some(y);
+        self.t = Some(t.clone());
+        self.loss = Some(loss);
+        loss
+    }
+
+    pub fn backward(&mut self, dout: f64) -> Array2<f64> {
+        let y = self
+            .y
+            .as_ref()
+            .expect("SoftmaxWithLoss::backward called before forward");
+        let t = self.t.as_ref().unwrap();
+        let batch_size = t.nrows() as f64;
+        (y - t) * dout / batch_size
+    }
+}
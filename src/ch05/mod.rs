@@ -0,0 +1,4 @@
+pub mod autograd;
+pub mod gradient_check;
+pub mod layers;
+pub mod model;
@@ -0,0 +1,123 @@
+use std::{collections::HashMap, io, path::Path};
+
+use ndarray::{Array, Array2, ArrayD, Axis, RemoveAxis, indices_of, s};
+use ndarray_stats::QuantileExt;
+
+use crate::{ch04::two_layer::Weight, persistence::params};
+
+/// Number of rows of scores `y` whose argmax matches `t` (one-hot or (N, 1) labels).
+pub fn count_correct(y: &Array2<f64>, t: &Array2<f64>) -> usize {
+    let y = y.map_axis(Axis(1), |y| y.argmax().unwrap());
+    let t = if t.ncols() == 1 {
+        t.column(0).mapv(|t| t as usize)
+    } else {
+        t.map_axis(Axis(1), |t| t.argmax().unwrap())
+    };
+    y.iter().zip(t.iter()).filter(|(y, t)| y == t).count()
+}
+
+/// Like `Model::accuracy`, but predicts `batch_size` samples at a time to bound memory use.
+pub fn batched_accuracy<M, D>(
+    model: &mut M,
+    x: &Array<f64, D>,
+    t: &Array2<f64>,
+    batch_size: usize,
+) -> f64
+where
+    M: Model<Input = Array<f64, D>> + ?Sized,
+    D: RemoveAxis,
+{
+    let n = x.len_of(Axis(0));
+    let mut correct = 0;
+    for start in (0..n).step_by(batch_size) {
+        let end = (start + batch_size).min(n);
+        let x_batch = x.slice_axis(Axis(0), (start..end).into()).to_owned();
+        let y = model.predict(&x_batch);
+        correct += count_correct(&y, &t.slice(s![start..end, ..]).to_owned());
+    }
+    correct as f64 / n as f64
+}
+
+/// A network whose parameters live in a `Weight` map and that can differentiate its loss
+/// both numerically and by backpropagation.
+pub trait Model {
+    /// Batch of inputs the model consumes, e.g. `Array2<f64>` for dense networks.
+    type Input;
+
+    fn params(&self) -> &HashMap<String, Weight>;
+
+    fn params_mut(&mut self) -> &mut HashMap<String, Weight>;
+
+    /// Scores of shape (N, classes).
+    fn predict(&mut self, x: &Self::Input) -> Array2<f64>;
+
+    fn loss(&mut self, x: &Self::Input, t: &Array2<f64>) -> f64;
+
+    /// Ratio of rows whose argmax matches `t`, which is either one-hot (N, classes) or labels (N, 1).
+    fn accuracy(&mut self, x: &Self::Input, t: &Array2<f64>) -> f64 {
+        let y = self.predict(x);
+        count_correct(&y, t) as f64 / y.nrows() as f64
+    }
+
+    /// Switch between training and inference behaviour of layers such as Dropout and
+    /// BatchNormalization. Models without such layers can ignore it.
+    fn set_train_flg(&mut self, _train_flg: bool) {}
+
+    fn train(&mut self) {
+        self.set_train_flg(true);
+    }
+
+    fn eval(&mut self) {
+        self.set_train_flg(false);
+    }
+
+    /// Write `params` to `path` in the format of `persistence::params`.
+    fn save_params(&self, path: impl AsRef<Path>) -> io::Result<()>
+    where
+        Self: Sized,
+    {
+        params::save_params(path, self.params())
+    }
+
+    /// Replace `params` with the ones saved at `path`. Names and shapes must match.
+    fn load_params(&mut self, path: impl AsRef<Path>) -> io::Result<()>
+    where
+        Self: Sized,
+    {
+        let loaded = params::load_params(path)?;
+        params::assign_params(self.params_mut(), loaded)
+    }
+
+    /// Gradients by backpropagation, keyed like `params`.
+    fn gradient(&mut self, x: &Self::Input, t: &Array2<f64>) -> HashMap<String, Weight>;
+
+    /// Gradients by central difference on every parameter element, keyed like `params`.
+    fn numerical_gradient(&mut self, x: &Self::Input, t: &Array2<f64>) -> HashMap<String, Weight> {
+        let h = 1e-4;
+        let keys = self.params().keys().cloned().collect::<Vec<_>>();
+        let mut grads = HashMap::new();
+
+        for key in keys {
+            let param = self.params()[&key].view().to_owned();
+            let mut grad = ArrayD::zeros(param.raw_dim());
+
+            for idx in indices_of(&param) {
+                let v = param[&idx];
+                // f(x+h)
+                self.params_mut().get_mut(&key).unwrap().view_mut()[&idx] = v + h;
+                let fxh1 = self.loss(x, t);
+
+                // f(x-h)
+                self.params_mut().get_mut(&key).unwrap().view_mut()[&idx] = v - h;
+                let fxh2 = self.loss(x, t);
+
+                grad[&idx] = (fxh1 - fxh2) / (2. * h);
+                self.params_mut().get_mut(&key).unwrap().view_mut()[&idx] = v;
+            }
+
+            grads.insert(key, Weight::from_dyn(grad));
+        }
+
+        grads
+    }
+}
@@ -0,0 +1,125 @@
+use std::collections::HashMap;
+
+use ndarray::{Array1, Array2, Axis};
+
+use crate::{ch04::two_layer::Weight, ch05::layers::Layer};
+
+/// Batch Normalization (http://arxiv.org/abs/1502.03167)
+///
+/// In training mode every mini-batch is normalized with its own mean and variance, which are
+/// also folded into `running_mean`/`running_var`. In inference mode the running statistics are
+/// used instead, so the output of a sample does not depend on the rest of the batch.
+/// A new layer starts in training mode.
+#[derive(Clone, Debug)]
+pub struct BatchNormalization {
+    pub gamma: Array1<f64>,
+    pub beta: Array1<f64>,
+    pub momentum: f64,
+    pub running_mean: Option<Array1<f64>>,
+    pub running_var: Option<Array1<f64>>,
+    train_flg: bool,
+
+    // intermediate data for backward
+    xc: Option<Array2<f64>>,
+    xn: Option<Array2<f64>>,
+    std: Option<Array1<f64>>,
+
+    pub dgamma: Array1<f64>,
+    pub dbeta: Array1<f64>,
+}
+
+impl BatchNormalization {
+    pub fn new(gamma: Array1<f64>, beta: Array1<f64>, momentum: Option<f64>) -> Self {
+        let dgamma = Array1::zeros(gamma.raw_dim());
+        let dbeta = Array1::zeros(beta.raw_dim());
+        Self {
+            gamma,
+            beta,
+            momentum: momentum.unwrap_or(0.9),
+            running_mean: None,
+            running_var: None,
+            train_flg: true,
+            xc: None,
+            xn: None,
+            std: None,
+            dgamma,
+            dbeta,
+        }
+    }
+}
+
+impl Layer for BatchNormalization {
+    fn forward(&mut self, x: &Array2<f64>) -> Array2<f64> {
+        let d = x.ncols();
+        let running_mean = self.running_mean.get_or_insert_with(|| Array1::zeros(d));
+        let running_var = self.running_var.get_or_insert_with(|| Array1::zeros(d));
+
+        let xn = if self.train_flg {
+            let mu = x.mean_axis(Axis(0)).unwrap();
+            let xc = x - &mu;
+            let var = xc.mapv(|x| x * x).mean_axis(Axis(0)).unwrap();
+            let std = var.mapv(|v| (v + 10e-7).sqrt());
+            let xn = &xc / &std;
+
+            *running_mean = self.momentum * &*running_mean + (1. - self.momentum) * &mu;
+            *running_var = self.momentum * &*running_var + (1. - self.momentum) * &var;
+
+            self.xc = Some(xc);
+            self.xn = Some(xn.clone());
+            self.std = Some(std);
+            xn
+        } else {
+            let xc = x - &*running_mean;
+            xc / running_var.mapv(|v| (v + 10e-7).sqrt())
+        };
+
+        xn * &self.gamma + &self.beta
+    }
+
+    fn backward(&mut self, dout: &Array2<f64>) -> Array2<f64> {
+        let xc = self
+            .xc
+            .as_ref()
+            .expect("BatchNormalization::backward called before a training forward");
+        let xn = self.xn.as_ref().unwrap();
+        let std = self.std.as_ref().unwrap();
+        let batch_size = dout.nrows() as f64;
+
+        self.dbeta = dout.sum_axis(Axis(0));
+        self.dgamma = (xn * dout).sum_axis(Axis(0));
+        let dxn = dout * &self.gamma;
+        let mut dxc = &dxn / std;
+        let dstd = -(&dxn * xc / (std * std)).sum_axis(Axis(0));
+        let dvar = 0.5 * dstd / std;
+        dxc = dxc + (2. / batch_size) * xc * &dvar;
+        let dmu = dxc.sum_axis(Axis(0));
+        dxc - dmu / batch_size
+    }
+
+    fn params(&self) -> HashMap<String, Weight> {
+        HashMap::from([
+            ("gamma".to_owned(), Weight::M1(self.gamma.clone())),
+            ("beta".to_owned(), Weight::M1(self.beta.clone())),
+        ])
+    }
+
+    fn set_params(&mut self, params: &HashMap<String, Weight>) {
+        if let Some(gamma) = params.get("gamma") {
+            self.gamma = gamma.unwrap_m1();
+        }
+        if let Some(beta) = params.get("beta") {
+            self.beta = beta.unwrap_m1();
+        }
+    }
+
+    fn grads(&self) -> HashMap<String, Weight> {
+        HashMap::from([
+            ("gamma".to_owned(), Weight::M1(self.dgamma.clone())),
+            ("beta".to_owned(), Weight::M1(self.dbeta.clone())),
+        ])
+    }
+
+    fn set_train_flg(&mut self, train_flg: bool) {
+        self.train_flg = train_flg;
+    }
+}
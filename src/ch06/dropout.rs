@@ -0,0 +1,78 @@
+use ndarray::{Array, Dimension, Ix2, Zip};
+use ndarray_rand::{RandomExt, rand::SeedableRng, rand_distr::Uniform};
+use rand_chacha::ChaCha8Rng;
+
+use crate::ch05::layers::Layer;
+
+/// Dropout (http://arxiv.org/abs/1207.0580)
+///
+/// In training mode every element is dropped with probability `dropout_ratio`. In inference mode
+/// nothing is dropped and the output is scaled by `1 - dropout_ratio` instead, so the expected
+/// activation matches training. Masks come from a ChaCha8 stream, so a seeded layer drops the
+/// same units on every run. A new layer starts in training mode.
+#[derive(Clone, Debug)]
+pub struct Dropout<D = Ix2>
+where
+    D: Dimension,
+{
+    pub dropout_ratio: f64,
+    rng: ChaCha8Rng,
+    mask: Option<Array<bool, D>>,
+    train_flg: bool,
+}
+
+impl<D> Dropout<D>
+where
+    D: Dimension,
+{
+    pub fn new(dropout_ratio: Option<f64>, seed: Option<u64>) -> Self {
+        let dropout_ratio = dropout_ratio.unwrap_or(0.5);
+        assert!(
+            (0. ..1.).contains(&dropout_ratio),
+            "dropout_ratio must be in [0, 1)"
+        );
+        let rng = match seed {
+            Some(seed) => ChaCha8Rng::seed_from_u64(seed),
+            None => ChaCha8Rng::from_entropy(),
+        };
+        Self {
+            dropout_ratio,
+            rng,
+            mask: None,
+            train_flg: true,
+        }
+    }
+}
+
+impl<D> Layer<D> for Dropout<D>
+where
+    D: Dimension,
+{
+    fn forward(&mut self, x: &Array<f64, D>) -> Array<f64, D> {
+        if self.train_flg {
+            let mask = Array::random_using(x.raw_dim(), Uniform::new(0., 1.), &mut self.rng)
+                .mapv(|r| r > self.dropout_ratio);
+            let out = Zip::from(x)
+                .and(&mask)
+                .map_collect(|&x, &keep| if keep { x } else { 0. });
+            self.mask = Some(mask);
+            out
+        } else {
+            x * (1. - self.dropout_ratio)
+        }
+    }
+
+    fn backward(&mut self, dout: &Array<f64, D>) -> Array<f64, D> {
+        let mask = self
+            .mask
+            .as_ref()
+            .expect("Dropout::backward called before a training forward");
+        Zip::from(dout)
+            .and(mask)
+            .map_collect(|&d, &keep| if keep { d } else { 0. })
+    }
+
+    fn set_train_flg(&mut self, train_flg: bool) {
+        self.train_flg = train_flg;
+    }
+}
@@ -0,0 +1,336 @@
+use std::{collections::BTreeMap, f64::consts::PI, io};
+
+use crate::ch06::optimizer::Optimizer;
+
+/// Learning-rate schedule, kept apart from the optimizer so any pair can be combined.
+/// Whether a step is an iteration or an epoch is up to the training loop.
+pub trait LrScheduler {
+    fn learning_rate(&self) -> f64;
+
+    fn step(&mut self);
+
+    /// Metric measured at the end of an epoch (e.g. test accuracy).
+    /// Schedules that depend only on the step count ignore it.
+    fn observe(&mut self, _metric: f64) {}
+
+    /// Write the current learning rate into `optimizer`.
+    fn apply(&self, optimizer: &mut dyn Optimizer) {
+        optimizer.set_learning_rate(self.learning_rate());
+    }
+
+    /// Counters and metrics that change while stepping, for checkpoints.
+    fn state(&self) -> BTreeMap<String, f64> {
+        BTreeMap::new()
+    }
+
+    /// Restore what `state` returned. Fails without changing the schedule if `state` lacks a
+    /// key it needs.
+    fn load_state(&mut self, _state: &BTreeMap<String, f64>) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn state_value(state: &BTreeMap<String, f64>, key: &str) -> io::Result<f64> {
+    state.get(key).copied().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("scheduler state has no `{key}`"),
+        )
+    })
+}
+
+/// Multiply the learning rate by `gamma` every `step_size` steps.
+#[derive(Clone, Debug)]
+pub struct StepDecay {
+    pub base_lr: f64,
+    pub step_size: usize,
+    pub gamma: f64,
+    step: usize,
+}
+
+impl StepDecay {
+    pub fn new(base_lr: f64, step_size: usize, gamma: Option<f64>) -> Self {
+        assert!(step_size > 0, "step_size must be positive");
+        Self {
+            base_lr,
+            step_size,
+            gamma: gamma.unwrap_or(0.1),
+            step: 0,
+        }
+    }
+}
+
+impl LrScheduler for StepDecay {
+    fn learning_rate(&self) -> f64 {
+        self.base_lr * self.gamma.powi((self.step / self.step_size) as i32)
+    }
+
+    fn step(&mut self) {
+        self.step += 1;
+    }
+
+    fn state(&self) -> BTreeMap<String, f64> {
+        BTreeMap::from([("step".to_owned(), self.step as f64)])
+    }
+
+    fn load_state(&mut self, state: &BTreeMap<String, f64>) -> io::Result<()> {
+        self.step = state_value(state, "step")? as usize;
+        Ok(())
+    }
+}
+
+/// `lr = base_lr * gamma^step`
+#[derive(Clone, Debug)]
+pub struct ExponentialDecay {
+    pub base_lr: f64,
+    pub gamma: f64,
+    step: usize,
+}
+
+impl ExponentialDecay {
+    pub fn new(base_lr: f64, gamma: Option<f64>) -> Self {
+        Self {
+            base_lr,
+            gamma: gamma.unwrap_or(0.95),
+            step: 0,
+        }
+    }
+}
+
+impl LrScheduler for ExponentialDecay {
+    fn learning_rate(&self) -> f64 {
+        self.base_lr * self.gamma.powi(self.step as i32)
+    }
+
+    fn step(&mut self) {
+        self.step += 1;
+    }
+
+    fn state(&self) -> BTreeMap<String, f64> {
+        BTreeMap::from([("step".to_owned(), self.step as f64)])
+    }
+
+    fn load_state(&mut self, state: &BTreeMap<String, f64>) -> io::Result<()> {
+        self.step = state_value(state, "step")? as usize;
+        Ok(())
+    }
+}
+
+/// SGDR (https://arxiv.org/abs/1608.03983): cosine decay from `base_lr` to `min_lr` over a cycle,
+/// then restart. Every cycle is `t_mult` times longer than the previous one.
+#[derive(Clone, Debug)]
+pub struct CosineAnnealingWarmRestarts {
+    pub base_lr: f64,
+    pub min_lr: f64,
+    pub t_0: usize,
+    pub t_mult: usize,
+    t_cur: usize,
+    t_i: usize,
+}
+
+impl CosineAnnealingWarmRestarts {
+    pub fn new(base_lr: f64, t_0: usize, t_mult: Option<usize>, min_lr: Option<f64>) -> Self {
+        assert!(t_0 > 0, "t_0 must be positive");
+        let t_mult = t_mult.unwrap_or(1);
+        assert!(t_mult > 0, "t_mult must be positive");
+        Self {
+            base_lr,
+            min_lr: min_lr.unwrap_or(0.),
+            t_0,
+            t_mult,
+            t_cur: 0,
+            t_i: t_0,
+        }
+    }
+}
+
+impl LrScheduler for CosineAnnealingWarmRestarts {
+    fn learning_rate(&self) -> f64 {
+        let progress = self.t_cur as f64 / self.t_i as f64;
+        self.min_lr + (self.base_lr - self.min_lr) * (1. + (PI * progress).cos()) / 2.
+    }
+
+    fn step(&mut self) {
+        self.t_cur += 1;
+        if self.t_cur >= self.t_i {
+            self.t_cur -= self.t_i;
+            self.t_i *= self.t_mult;
+        }
+    }
+
+    fn state(&self) -> BTreeMap<String, f64> {
+        BTreeMap::from([
+            ("t_cur".to_owned(), self.t_cur as f64),
+            ("t_i".to_owned(), self.t_i as f64),
+        ])
+    }
+
+    fn load_state(&mut self, state: &BTreeMap<String, f64>) -> io::Result<()> {
+        let t_cur = state_value(state, "t_cur")? as usize;
+        self.t_i = state_value(state, "t_i")? as usize;
+        self.t_cur = t_cur;
+        Ok(())
+    }
+}
+
+/// Ramp the learning rate linearly up to the wrapped schedule over `warmup_steps`,
+/// then hand over to it. The wrapped schedule only starts stepping after the warmup.
+#[derive(Clone, Debug)]
+pub struct LinearWarmup<S> {
+    pub warmup_steps: usize,
+    scheduler: S,
+    step: usize,
+}
+
+impl<S> LinearWarmup<S>
+where
+    S: LrScheduler,
+{
+    pub fn new(scheduler: S, warmup_steps: usize) -> Self {
+        Self {
+            warmup_steps,
+            scheduler,
+            step: 0,
+        }
+    }
+}
+
+impl<S> LrScheduler for LinearWarmup<S>
+where
+    S: LrScheduler,
+{
+    fn learning_rate(&self) -> f64 {
+        let lr = self.scheduler.learning_rate();
+        if self.step < self.warmup_steps {
+            lr * (self.step + 1) as f64 / (self.warmup_steps + 1) as f64
+        } else {
+            lr
+        }
+    }
+
+    fn step(&mut self) {
+        if self.step < self.warmup_steps {
+            self.step += 1;
+        } else {
+            self.scheduler.step();
+        }
+    }
+
+    fn observe(&mut self, metric: f64) {
+        self.scheduler.observe(metric);
+    }
+
+    /// The wrapped schedule's entries are prefixed with `scheduler.`.
+    fn state(&self) -> BTreeMap<String, f64> {
+        let mut state = BTreeMap::from([("step".to_owned(), self.step as f64)]);
+        for (key, value) in self.scheduler.state() {
+            state.insert(format!("scheduler.{key}"), value);
+        }
+        state
+    }
+
+    fn load_state(&mut self, state: &BTreeMap<String, f64>) -> io::Result<()> {
+        let step = state_value(state, "step")? as usize;
+        let inner = state
+            .iter()
+            .filter_map(|(key, &value)| Some((key.strip_prefix("scheduler.")?.to_owned(), value)))
+            .collect();
+        self.scheduler.load_state(&inner)?;
+        self.step = step;
+        Ok(())
+    }
+}
+
+/// Whether a larger or a smaller metric is an improvement.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PlateauMode {
+    /// e.g. loss
+    Min,
+    /// e.g. accuracy
+    Max,
+}
+
+/// Multiply the learning rate by `factor` once the observed metric has not improved
+/// for more than `patience` epochs. Ignores `step`.
+#[derive(Clone, Debug)]
+pub struct ReduceOnPlateau {
+    pub mode: PlateauMode,
+    pub factor: f64,
+    pub patience: usize,
+    pub threshold: f64,
+    pub min_lr: f64,
+    lr: f64,
+    best: Option<f64>,
+    num_bad_epochs: usize,
+}
+
+impl ReduceOnPlateau {
+    pub fn new(
+        lr: f64,
+        mode: PlateauMode,
+        factor: Option<f64>,
+        patience: Option<usize>,
+        min_lr: Option<f64>,
+    ) -> Self {
+        Self {
+            mode,
+            factor: factor.unwrap_or(0.1),
+            patience: patience.unwrap_or(10),
+            threshold: 1e-4,
+            min_lr: min_lr.unwrap_or(0.),
+            lr,
+            best: None,
+            num_bad_epochs: 0,
+        }
+    }
+
+    fn is_better(&self, metric: f64, best: f64) -> bool {
+        match self.mode {
+            PlateauMode::Min => metric < best - self.threshold,
+            PlateauMode::Max => metric > best + self.threshold,
+        }
+    }
+}
+
+impl LrScheduler for ReduceOnPlateau {
+    fn learning_rate(&self) -> f64 {
+        self.lr
+    }
+
+    fn step(&mut self) {}
+
+    fn observe(&mut self, metric: f64) {
+        match self.best {
+            Some(best) if !self.is_better(metric, best) => self.num_bad_epochs += 1,
+            _ => {
+                self.best = Some(metric);
+                self.num_bad_epochs = 0;
+            }
+        }
+
+        if self.num_bad_epochs > self.patience {
+            self.lr = (self.lr * self.factor).max(self.min_lr);
+            self.num_bad_epochs = 0;
+        }
+    }
+
+    /// `best` is left out until a metric has been observed.
+    fn state(&self) -> BTreeMap<String, f64> {
+        let mut state = BTreeMap::from([
+            ("lr".to_owned(), self.lr),
+            ("num_bad_epochs".to_owned(), self.num_bad_epochs as f64),
+        ]);
+        if let Some(best) = self.best {
+            state.insert("best".to_owned(), best);
+        }
+        state
+    }
+
+    fn load_state(&mut self, state: &BTreeMap<String, f64>) -> io::Result<()> {
+        let lr = state_value(state, "lr")?;
+        self.num_bad_epochs = state_value(state, "num_bad_epochs")? as usize;
+        self.lr = lr;
+        self.best = state.get("best").copied();
+        Ok(())
+    }
+}
@@ -0,0 +1,8 @@
+pub mod batch_normalization;
+pub mod dropout;
+pub mod lr_scheduler;
+pub mod multi_layer_net;
+pub mod optimizer;
+pub mod trainer;
+pub mod weight_decay;
+pub mod weight_init;
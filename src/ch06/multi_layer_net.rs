@@ -0,0 +1,233 @@
+use std::collections::HashMap;
+
+use ndarray::{Array1, Array2};
+
+use crate::{
+    ch04::two_layer::Weight,
+    ch05::{
+        layers::{Affine, Layer, Relu, Sigmoid, SoftmaxWithLoss},
+        model::Model,
+    },
+    ch06::{
+        batch_normalization::BatchNormalization, dropout::Dropout, weight_decay::WeightDecay,
+        weight_init::WeightInit,
+    },
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Activation {
+    Relu,
+    Sigmoid,
+}
+
+impl Activation {
+    fn layer(&self) -> Box<dyn Layer> {
+        match self {
+            Self::Relu => Box::new(Relu::new()),
+            Self::Sigmoid => Box::new(Sigmoid::new()),
+        }
+    }
+}
+
+/// Fully connected network of any depth: (Affine -> [BatchNorm] -> activation -> [Dropout]) for
+/// every hidden layer, then Affine -> SoftmaxWithLoss. Parameters are `w1..wN`/`b1..bN` with
+/// N = hidden layers + 1, plus `gamma1..`/`beta1..` for the hidden layers when batch
+/// normalization is enabled. A new network is in inference mode.
+pub struct MultiLayerNet {
+    params: HashMap<String, Weight>,
+    /// Every layer is tagged with its block number; parameter `w` of block 2 is stored as `w2`.
+    layers: Vec<(usize, Box<dyn Layer>)>,
+    last_layer: SoftmaxWithLoss,
+    train_flg: bool,
+
+    hidden_size_list: Vec<usize>,
+    activation: Activation,
+    use_batchnorm: bool,
+    dropout: Option<(f64, Option<u64>)>,
+    weight_decay: WeightDecay,
+}
+
+impl MultiLayerNet {
+    pub fn new(
+        input_size: usize,
+        hidden_size_list: &[usize],
+        output_size: usize,
+        activation: Activation,
+        weight_init: WeightInit,
+    ) -> Self {
+        let mut sizes = vec![input_size];
+        sizes.extend_from_slice(hidden_size_list);
+        sizes.push(output_size);
+
+        let mut params = HashMap::new();
+        for (idx, pair) in sizes.windows(2).enumerate() {
+            let w = weight_init.dense(pair[0], pair[1]);
+            let b = Array1::zeros(pair[1]);
+            params.insert(format!("w{}", idx + 1), Weight::M2(w));
+            params.insert(format!("b{}", idx + 1), Weight::M1(b));
+        }
+
+        let mut network = Self {
+            params,
+            layers: Vec::new(),
+            last_layer: SoftmaxWithLoss::new(),
+            train_flg: false,
+            hidden_size_list: hidden_size_list.to_vec(),
+            activation,
+            use_batchnorm: false,
+            dropout: None,
+            weight_decay: WeightDecay::default(),
+        };
+        network.build_layers();
+        network
+    }
+
+    /// Insert a `BatchNormalization` between every hidden Affine and its activation.
+    pub fn with_batchnorm(mut self) -> Self {
+        for (idx, &hidden_size) in self.hidden_size_list.iter().enumerate() {
+            self.params.insert(
+                format!("gamma{}", idx + 1),
+                Weight::M1(Array1::ones(hidden_size)),
+            );
+            self.params.insert(
+                format!("beta{}", idx + 1),
+                Weight::M1(Array1::zeros(hidden_size)),
+            );
+        }
+        self.use_batchnorm = true;
+        self.build_layers();
+        self
+    }
+
+    /// Insert a `Dropout` after every hidden activation. With a seed, the layer of block `i`
+    /// is seeded with `seed + i`.
+    pub fn with_dropout(mut self, dropout_ratio: f64, seed: Option<u64>) -> Self {
+        self.dropout = Some((dropout_ratio, seed));
+        self.build_layers();
+        self
+    }
+
+    /// Add an L1/L2 penalty on every `w*` to the loss and to both gradients.
+    pub fn with_weight_decay(mut self, weight_decay: WeightDecay) -> Self {
+        self.weight_decay = weight_decay;
+        self
+    }
+
+    pub fn train_flg(&self) -> bool {
+        self.train_flg
+    }
+
+    fn build_layers(&mut self) {
+        let mut layers: Vec<(usize, Box<dyn Layer>)> = Vec::new();
+        let affine = |idx: usize| {
+            Affine::new(
+                self.params[&format!("w{idx}")].unwrap_m2(),
+                self.params[&format!("b{idx}")].unwrap_m1(),
+            )
+        };
+
+        for idx in 1..=self.hidden_size_list.len() {
+            layers.push((idx, Box::new(affine(idx))));
+            if self.use_batchnorm {
+                let batch_norm = BatchNormalization::new(
+                    self.params[&format!("gamma{idx}")].unwrap_m1(),
+                    self.params[&format!("beta{idx}")].unwrap_m1(),
+                    None,
+                );
+                layers.push((idx, Box::new(batch_norm)));
+            }
+            layers.push((idx, self.activation.layer()));
+            if let Some((dropout_ratio, seed)) = self.dropout {
+                let seed = seed.map(|seed| seed + idx as u64);
+                layers.push((idx, Box::new(Dropout::new(Some(dropout_ratio), seed))));
+            }
+        }
+        let idx = self.hidden_size_list.len() + 1;
+        layers.push((idx, Box::new(affine(idx))));
+
+        self.layers = layers;
+    }
+
+    /// Push the current parameters and mode into the layers.
+    fn sync_params(&mut self) {
+        let mut blocks: HashMap<usize, HashMap<String, Weight>> = HashMap::new();
+        for (key, param) in &self.params {
+            let (name, idx) = split_key(key);
+            blocks
+                .entry(idx)
+                .or_default()
+                .insert(name.to_owned(), param.clone());
+        }
+
+        for (idx, layer) in self.layers.iter_mut() {
+            if let Some(block) = blocks.get(idx) {
+                layer.set_params(block);
+            }
+            layer.set_train_flg(self.train_flg);
+        }
+    }
+}
+
+/// `"gamma12"` -> `("gamma", 12)`
+pub(crate) fn split_key(key: &str) -> (&str, usize) {
+    let pos = key
+        .find(|c: char| c.is_ascii_digit())
+        .unwrap_or_else(|| panic!("parameter `{key}` has no block number"));
+    (&key[..pos], key[pos..].parse().unwrap())
+}
+
+impl Model for MultiLayerNet {
+    type Input = Array2<f64>;
+
+    fn params(&self) -> &HashMap<String, Weight> {
+        &self.params
+    }
+
+    fn params_mut(&mut self) -> &mut HashMap<String, Weight> {
+        &mut self.params
+    }
+
+    /// Training mode (`true`) or inference mode (`false`) for `predict`, `loss` and `accuracy`.
+    fn set_train_flg(&mut self, train_flg: bool) {
+        self.train_flg = train_flg;
+    }
+
+    fn predict(&mut self, x: &Array2<f64>) -> Array2<f64> {
+        self.sync_params();
+        let mut x = x.clone();
+        for (_, layer) in self.layers.iter_mut() {
+            x = layer.forward(&x);
+        }
+        x
+    }
+
+    fn loss(&mut self, x: &Array2<f64>, t: &Array2<f64>) -> f64 {
+        let y = self.predict(x);
+        self.last_layer.forward(&y, t) + self.weight_decay.penalty(&self.params)
+    }
+
+    /// Always differentiates the training-mode forward pass, whatever `train_flg` is.
+    fn gradient(&mut self, x: &Array2<f64>, t: &Array2<f64>) -> HashMap<String, Weight> {
+        // forward
+        let train_flg = self.train_flg;
+        self.train_flg = true;
+        self.loss(x, t);
+        self.train_flg = train_flg;
+
+        // backward
+        let mut dout = self.last_layer.backward(1.);
+        for (_, layer) in self.layers.iter_mut().rev() {
+            dout = layer.backward(&dout);
+        }
+
+        let mut grads = HashMap::new();
+        for (idx, layer) in self.layers.iter() {
+            for (name, grad) in layer.grads() {
+                grads.insert(format!("{name}{idx}"), grad);
+            }
+        }
+        self.weight_decay.add_gradient(&self.params, &mut grads);
+
+        grads
+    }
+}
@@ -0,0 +1,400 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    io,
+};
+
+use ndarray::{ArrayD, Zip};
+
+use crate::ch04::two_layer::Weight;
+
+/// Updates a parameter map in place from the gradients of the same keys.
+/// Optimizers with state keep it per parameter name, so one instance must not be shared between models.
+pub trait Optimizer {
+    fn update(&mut self, params: &mut HashMap<String, Weight>, grads: &HashMap<String, Weight>);
+
+    fn learning_rate(&self) -> f64;
+
+    fn set_learning_rate(&mut self, lr: f64);
+
+    /// Everything `update` has accumulated so far, for checkpoints. The learning rate is not
+    /// part of it.
+    fn state(&self) -> OptimizerState {
+        OptimizerState::default()
+    }
+
+    /// Restore what `state` returned. Fails without changing the optimizer if `state` lacks a
+    /// slot or scalar it needs.
+    fn load_state(&mut self, _state: OptimizerState) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Optimizer state: counters in `scalars`, and per-parameter arrays (e.g. Adam's `m` and `v`)
+/// in `slots`, keyed by slot name and then by parameter name.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct OptimizerState {
+    pub scalars: BTreeMap<String, f64>,
+    pub slots: BTreeMap<String, HashMap<String, ArrayD<f64>>>,
+}
+
+impl OptimizerState {
+    fn from_slots<const N: usize>(slots: [(&str, &HashMap<String, ArrayD<f64>>); N]) -> Self {
+        Self {
+            scalars: BTreeMap::new(),
+            slots: slots
+                .into_iter()
+                .map(|(name, slot)| (name.to_owned(), slot.clone()))
+                .collect(),
+        }
+    }
+
+    fn take_slot(&mut self, name: &str) -> io::Result<HashMap<String, ArrayD<f64>>> {
+        self.slots.remove(name).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("optimizer state has no slot `{name}`"),
+            )
+        })
+    }
+
+    fn scalar(&self, name: &str) -> io::Result<f64> {
+        self.scalars.get(name).copied().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("optimizer state has no scalar `{name}`"),
+            )
+        })
+    }
+}
+
+/// Stochastic Gradient Descent: `w <- w - lr * dw`
+#[derive(Clone, Debug)]
+pub struct Sgd {
+    pub lr: f64,
+}
+
+impl Sgd {
+    pub fn new(lr: Option<f64>) -> Self {
+        Self {
+            lr: lr.unwrap_or(0.01),
+        }
+    }
+}
+
+impl Optimizer for Sgd {
+    fn learning_rate(&self) -> f64 {
+        self.lr
+    }
+
+    fn set_learning_rate(&mut self, lr: f64) {
+        self.lr = lr;
+    }
+
+    fn update(&mut self, params: &mut HashMap<String, Weight>, grads: &HashMap<String, Weight>) {
+        for (key, grad) in grads {
+            let mut param = param_mut(params, key);
+            param.scaled_add(-self.lr, &grad.view());
+        }
+    }
+}
+
+/// Momentum SGD: `v <- momentum * v - lr * dw`, `w <- w + v`
+#[derive(Clone, Debug)]
+pub struct Momentum {
+    pub lr: f64,
+    pub momentum: f64,
+    v: HashMap<String, ArrayD<f64>>,
+}
+
+impl Momentum {
+    pub fn new(lr: Option<f64>, momentum: Option<f64>) -> Self {
+        Self {
+            lr: lr.unwrap_or(0.01),
+            momentum: momentum.unwrap_or(0.9),
+            v: HashMap::new(),
+        }
+    }
+}
+
+impl Optimizer for Momentum {
+    fn learning_rate(&self) -> f64 {
+        self.lr
+    }
+
+    fn set_learning_rate(&mut self, lr: f64) {
+        self.lr = lr;
+    }
+
+    fn state(&self) -> OptimizerState {
+        OptimizerState::from_slots([("v", &self.v)])
+    }
+
+    fn load_state(&mut self, mut state: OptimizerState) -> io::Result<()> {
+        self.v = state.take_slot("v")?;
+        Ok(())
+    }
+
+    fn update(&mut self, params: &mut HashMap<String, Weight>, grads: &HashMap<String, Weight>) {
+        for (key, grad) in grads {
+            let grad = grad.view();
+            let v = state(&mut self.v, key, &grad.raw_dim());
+            let mut param = param_mut(params, key);
+            let (lr, momentum) = (self.lr, self.momentum);
+            Zip::from(&mut param)
+                .and(v)
+                .and(&grad)
+                .for_each(|p, v, &g| {
+                    *v = momentum * *v - lr * g;
+                    *p += *v;
+                });
+        }
+    }
+}
+
+/// Nesterov's Accelerated Gradient (http://arxiv.org/abs/1212.0901)
+#[derive(Clone, Debug)]
+pub struct Nesterov {
+    pub lr: f64,
+    pub momentum: f64,
+    v: HashMap<String, ArrayD<f64>>,
+}
+
+impl Nesterov {
+    pub fn new(lr: Option<f64>, momentum: Option<f64>) -> Self {
+        Self {
+            lr: lr.unwrap_or(0.01),
+            momentum: momentum.unwrap_or(0.9),
+            v: HashMap::new(),
+        }
+    }
+}
+
+impl Optimizer for Nesterov {
+    fn learning_rate(&self) -> f64 {
+        self.lr
+    }
+
+    fn set_learning_rate(&mut self, lr: f64) {
+        self.lr = lr;
+    }
+
+    fn state(&self) -> OptimizerState {
+        OptimizerState::from_slots([("v", &self.v)])
+    }
+
+    fn load_state(&mut self, mut state: OptimizerState) -> io::Result<()> {
+        self.v = state.take_slot("v")?;
+        Ok(())
+    }
+
+    fn update(&mut self, params: &mut HashMap<String, Weight>, grads: &HashMap<String, Weight>) {
+        for (key, grad) in grads {
+            let grad = grad.view();
+            let v = state(&mut self.v, key, &grad.raw_dim());
+            let mut param = param_mut(params, key);
+            let (lr, momentum) = (self.lr, self.momentum);
+            Zip::from(&mut param)
+                .and(v)
+                .and(&grad)
+                .for_each(|p, v, &g| {
+                    *v = momentum * *v - lr * g;
+                    *p += momentum * momentum * *v - (1. + momentum) * lr * g;
+                });
+        }
+    }
+}
+
+/// AdaGrad: the learning rate of each element decays with its accumulated squared gradient.
+#[derive(Clone, Debug)]
+pub struct AdaGrad {
+    pub lr: f64,
+    h: HashMap<String, ArrayD<f64>>,
+}
+
+impl AdaGrad {
+    pub fn new(lr: Option<f64>) -> Self {
+        Self {
+            lr: lr.unwrap_or(0.01),
+            h: HashMap::new(),
+        }
+    }
+}
+
+impl Optimizer for AdaGrad {
+    fn learning_rate(&self) -> f64 {
+        self.lr
+    }
+
+    fn set_learning_rate(&mut self, lr: f64) {
+        self.lr = lr;
+    }
+
+    fn state(&self) -> OptimizerState {
+        OptimizerState::from_slots([("h", &self.h)])
+    }
+
+    fn load_state(&mut self, mut state: OptimizerState) -> io::Result<()> {
+        self.h = state.take_slot("h")?;
+        Ok(())
+    }
+
+    fn update(&mut self, params: &mut HashMap<String, Weight>, grads: &HashMap<String, Weight>) {
+        for (key, grad) in grads {
+            let grad = grad.view();
+            let h = state(&mut self.h, key, &grad.raw_dim());
+            let mut param = param_mut(params, key);
+            let lr = self.lr;
+            Zip::from(&mut param)
+                .and(h)
+                .and(&grad)
+                .for_each(|p, h, &g| {
+                    *h += g * g;
+                    *p -= lr * g / (h.sqrt() + 1e-7);
+                });
+        }
+    }
+}
+
+/// RMSProp: like AdaGrad but with an exponential moving average, so old gradients are forgotten.
+#[derive(Clone, Debug)]
+pub struct RmsProp {
+    pub lr: f64,
+    pub decay_rate: f64,
+    h: HashMap<String, ArrayD<f64>>,
+}
+
+impl RmsProp {
+    pub fn new(lr: Option<f64>, decay_rate: Option<f64>) -> Self {
+        Self {
+            lr: lr.unwrap_or(0.01),
+            decay_rate: decay_rate.unwrap_or(0.99),
+            h: HashMap::new(),
+        }
+    }
+}
+
+impl Optimizer for RmsProp {
+    fn learning_rate(&self) -> f64 {
+        self.lr
+    }
+
+    fn set_learning_rate(&mut self, lr: f64) {
+        self.lr = lr;
+    }
+
+    fn state(&self) -> OptimizerState {
+        OptimizerState::from_slots([("h", &self.h)])
+    }
+
+    fn load_state(&mut self, mut state: OptimizerState) -> io::Result<()> {
+        self.h = state.take_slot("h")?;
+        Ok(())
+    }
+
+    fn update(&mut self, params: &mut HashMap<String, Weight>, grads: &HashMap<String, Weight>) {
+        for (key, grad) in grads {
+            let grad = grad.view();
+            let h = state(&mut self.h, key, &grad.raw_dim());
+            let mut param = param_mut(params, key);
+            let (lr, decay_rate) = (self.lr, self.decay_rate);
+            Zip::from(&mut param)
+                .and(h)
+                .and(&grad)
+                .for_each(|p, h, &g| {
+                    *h = decay_rate * *h + (1. - decay_rate) * g * g;
+                    *p -= lr * g / (h.sqrt() + 1e-7);
+                });
+        }
+    }
+}
+
+/// Adam (http://arxiv.org/abs/1412.6980v8)
+#[derive(Clone, Debug)]
+pub struct Adam {
+    pub lr: f64,
+    pub beta1: f64,
+    pub beta2: f64,
+    iter: i32,
+    m: HashMap<String, ArrayD<f64>>,
+    v: HashMap<String, ArrayD<f64>>,
+}
+
+impl Adam {
+    pub fn new(lr: Option<f64>, beta1: Option<f64>, beta2: Option<f64>) -> Self {
+        Self {
+            lr: lr.unwrap_or(0.001),
+            beta1: beta1.unwrap_or(0.9),
+            beta2: beta2.unwrap_or(0.999),
+            iter: 0,
+            m: HashMap::new(),
+            v: HashMap::new(),
+        }
+    }
+}
+
+impl Optimizer for Adam {
+    fn learning_rate(&self) -> f64 {
+        self.lr
+    }
+
+    fn set_learning_rate(&mut self, lr: f64) {
+        self.lr = lr;
+    }
+
+    fn state(&self) -> OptimizerState {
+        let mut state = OptimizerState::from_slots([("m", &self.m), ("v", &self.v)]);
+        state.scalars.insert("iter".to_owned(), self.iter as f64);
+        state
+    }
+
+    fn load_state(&mut self, mut state: OptimizerState) -> io::Result<()> {
+        let iter = state.scalar("iter")? as i32;
+        let (m, v) = (state.take_slot("m")?, state.take_slot("v")?);
+        (self.iter, self.m, self.v) = (iter, m, v);
+        Ok(())
+    }
+
+    fn update(&mut self, params: &mut HashMap<String, Weight>, grads: &HashMap<String, Weight>) {
+        self.iter += 1;
+        let (beta1, beta2) = (self.beta1, self.beta2);
+        // bias correction folded into the step size
+        let lr_t = self.lr * (1. - beta2.powi(self.iter)).sqrt() / (1. - beta1.powi(self.iter));
+
+        for (key, grad) in grads {
+            let grad = grad.view();
+            let m = state(&mut self.m, key, &grad.raw_dim());
+            let v = state(&mut self.v, key, &grad.raw_dim());
+            let mut param = param_mut(params, key);
+            Zip::from(&mut param)
+                .and(m)
+                .and(v)
+                .and(&grad)
+                .for_each(|p, m, v, &g| {
+                    *m += (1. - beta1) * (g - *m);
+                    *v += (1. - beta2) * (g * g - *v);
+                    *p -= lr_t * *m / (v.sqrt() + 1e-7);
+                });
+        }
+    }
+}
+
+fn param_mut<'a>(
+    params: &'a mut HashMap<String, Weight>,
+    key: &str,
+) -> ndarray::ArrayViewMutD<'a, f64> {
+    params
+        .get_mut(key)
+        .unwrap_or_else(|| panic!("no parameter `{key}` for gradient"))
+        .view_mut()
+}
+
+/// Per-parameter optimizer state, zero-initialized on first use.
+fn state<'a>(
+    states: &'a mut HashMap<String, ArrayD<f64>>,
+    key: &str,
+    dim: &ndarray::IxDyn,
+) -> &'a mut ArrayD<f64> {
+    states
+        .entry(key.to_owned())
+        .or_insert_with(|| ArrayD::zeros(dim.clone()))
+}
@@ -0,0 +1,478 @@
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+
+use ndarray::{Array, Array2, Axis, RemoveAxis, s};
+use ndarray_rand::rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+
+use crate::{
+    ch05::model::Model,
+    ch06::{lr_scheduler::LrScheduler, optimizer::Optimizer},
+    dataset::{
+        data_loader::{ArrayDataset, Batches, DataLoader, Sampler},
+        transform::Transform,
+    },
+    persistence::{checkpoint::Checkpoint, params::assign_params},
+};
+
+/// Everything recorded while training. Accuracies are appended once per epoch.
+#[derive(Clone, Debug, Default)]
+pub struct TrainingHistory {
+    pub train_loss_list: Vec<f64>,
+    pub train_acc_list: Vec<f64>,
+    pub test_acc_list: Vec<f64>,
+}
+
+/// Hooks into the training loop. Both methods default to doing nothing.
+pub trait Callback {
+    /// `iteration` counts from 1 over the whole run.
+    fn on_batch_end(&mut self, _iteration: usize, _loss: f64) {}
+
+    /// `epoch` counts from 1. `history` already contains this epoch's accuracies.
+    fn on_epoch_end(&mut self, _epoch: usize, _history: &TrainingHistory) {}
+}
+
+/// Prints the loss every `loss_interval` iterations and the accuracies after every epoch.
+#[derive(Clone, Debug)]
+pub struct PrintProgress {
+    pub loss_interval: Option<usize>,
+}
+
+impl Callback for PrintProgress {
+    fn on_batch_end(&mut self, iteration: usize, loss: f64) {
+        if let Some(interval) = self.loss_interval
+            && iteration.is_multiple_of(interval)
+        {
+            println!("iter {iteration} | train loss: {loss}");
+        }
+    }
+
+    fn on_epoch_end(&mut self, epoch: usize, history: &TrainingHistory) {
+        println!(
+            "=== epoch: {epoch} | train acc: {:?} | test acc: {:?} ===",
+            history.train_acc_list.last().unwrap(),
+            history.test_acc_list.last().unwrap()
+        );
+    }
+}
+
+/// Mini-batch training loop shared by every `Model`.
+/// Each iteration trains on one batch of a `DataLoader` over the training data; by default every
+/// batch is sampled at random and `train_size / batch_size` iterations make an epoch.
+pub struct Trainer<M, O, D>
+where
+    M: Model<Input = Array<f64, D>>,
+    O: Optimizer,
+    D: RemoveAxis + Send + 'static,
+{
+    pub network: M,
+    pub optimizer: O,
+    train_loader: DataLoader<ArrayDataset<D>, D>,
+    /// The rest of the current epoch, `None` before it is drawn.
+    batches: Option<Batches<ArrayDataset<D>, D>>,
+    /// `train_loader`'s stream before the current epoch was drawn.
+    epoch_rng: ChaCha8Rng,
+    x_test: Array<f64, D>,
+    t_test: Array2<f64>,
+    epochs: usize,
+    evaluate_sample_num_per_epoch: Option<usize>,
+    scheduler: Option<Box<dyn LrScheduler>>,
+    callbacks: Vec<Box<dyn Callback>>,
+    checkpoint: Option<(PathBuf, usize)>,
+    current_iter: usize,
+    current_epoch: usize,
+    history: TrainingHistory,
+}
+
+impl<M, O, D> Trainer<M, O, D>
+where
+    M: Model<Input = Array<f64, D>>,
+    O: Optimizer,
+    D: RemoveAxis + Send + 'static,
+{
+    pub fn new(
+        network: M,
+        optimizer: O,
+        (x_train, t_train): (Array<f64, D>, Array2<f64>),
+        (x_test, t_test): (Array<f64, D>, Array2<f64>),
+        epochs: usize,
+        batch_size: usize,
+    ) -> Self {
+        assert_eq!(x_test.len_of(Axis(0)), t_test.nrows());
+        let train_loader = DataLoader::new(ArrayDataset::new(x_train, t_train), batch_size)
+            .with_sampler(Sampler::RandomBatches);
+        Self {
+            network,
+            optimizer,
+            epoch_rng: train_loader.rng().clone(),
+            train_loader,
+            batches: None,
+            x_test,
+            t_test,
+            epochs,
+            evaluate_sample_num_per_epoch: None,
+            scheduler: None,
+            callbacks: Vec::new(),
+            checkpoint: None,
+            current_iter: 0,
+            current_epoch: 0,
+            history: TrainingHistory::default(),
+        }
+    }
+
+    /// Evaluate accuracies on the first `n` samples only, to keep epochs cheap.
+    pub fn with_evaluate_sample_num(mut self, n: usize) -> Self {
+        self.evaluate_sample_num_per_epoch = Some(n);
+        self
+    }
+
+    /// Sets the optimizer's learning rate right away, so the first epoch already follows the
+    /// schedule, and is stepped once per epoch after observing the test accuracy.
+    pub fn with_scheduler(mut self, scheduler: impl LrScheduler + 'static) -> Self {
+        scheduler.apply(&mut self.optimizer);
+        self.scheduler = Some(Box::new(scheduler));
+        self
+    }
+
+    pub fn with_callback(mut self, callback: impl Callback + 'static) -> Self {
+        self.callbacks.push(Box::new(callback));
+        self
+    }
+
+    /// Sample mini-batches from a seeded ChaCha8 stream instead of an entropy-seeded one.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.train_loader.set_rng(ChaCha8Rng::seed_from_u64(seed));
+        self.epoch_rng = self.train_loader.rng().clone();
+        self
+    }
+
+    /// How each epoch visits the training data, `Sampler::RandomBatches` by default.
+    pub fn with_sampler(mut self, sampler: Sampler) -> Self {
+        self.train_loader = self.train_loader.with_sampler(sampler);
+        self
+    }
+
+    /// Skip the last, smaller batch of an epoch, see `DataLoader::with_drop_last`.
+    pub fn with_drop_last(mut self, drop_last: bool) -> Self {
+        self.train_loader = self.train_loader.with_drop_last(drop_last);
+        self
+    }
+
+    /// Applied to training batches only; accuracies are measured on the untransformed data.
+    pub fn with_transform(mut self, transform: impl Transform<D> + 'static) -> Self {
+        self.train_loader = self.train_loader.with_transform(transform);
+        self
+    }
+
+    /// Assemble training batches in the background, see `DataLoader::with_prefetch`.
+    pub fn with_prefetch(mut self, batches: usize, workers: usize) -> Self {
+        self.train_loader = self.train_loader.with_prefetch(batches, workers);
+        self
+    }
+
+    /// Overwrite a checkpoint at `path` every `interval` iterations.
+    pub fn with_checkpoint(mut self, path: impl Into<PathBuf>, interval: usize) -> Self {
+        assert!(interval > 0, "interval must be positive");
+        self.checkpoint = Some((path.into(), interval));
+        self
+    }
+
+    pub fn history(&self) -> &TrainingHistory {
+        &self.history
+    }
+
+    fn iter_per_epoch(&self) -> usize {
+        let iter_per_epoch = self.train_loader.num_batches();
+        assert!(iter_per_epoch > 0, "an epoch has no batches");
+        iter_per_epoch
+    }
+
+    pub fn train_step(&mut self) {
+        let position = self.current_iter % self.iter_per_epoch();
+        if position == 0 || self.batches.is_none() {
+            // after `resume` the epoch is drawn again and the batches already trained on skipped
+            self.epoch_rng = self.train_loader.rng().clone();
+            self.batches = Some(self.train_loader.epoch_from(position));
+        }
+        let (x_batch, t_batch) = self.batches.as_mut().unwrap().next().unwrap();
+
+        self.network.train();
+        let grads = self.network.gradient(&x_batch, &t_batch);
+        self.optimizer.update(self.network.params_mut(), &grads);
+
+        // loss and accuracies are measured in inference mode
+        self.network.eval();
+        let loss = self.network.loss(&x_batch, &t_batch);
+        self.history.train_loss_list.push(loss);
+        self.current_iter += 1;
+        for callback in &mut self.callbacks {
+            callback.on_batch_end(self.current_iter, loss);
+        }
+
+        if self.current_iter.is_multiple_of(self.iter_per_epoch()) {
+            self.current_epoch += 1;
+            self.end_epoch();
+        }
+
+        if let Some((path, interval)) = &self.checkpoint
+            && self.current_iter.is_multiple_of(*interval)
+        {
+            self.save_checkpoint(path)
+                .unwrap_or_else(|err| panic!("failed to save {}: {err}", path.display()));
+        }
+    }
+
+    fn end_epoch(&mut self) {
+        let train_set = self.train_loader.dataset();
+        let (x_train, t_train) = (train_set.x(), train_set.t());
+        let (train_acc, test_acc) = match self.evaluate_sample_num_per_epoch {
+            Some(n) => {
+                let n_train = n.min(x_train.len_of(Axis(0)));
+                let n_test = n.min(self.x_test.len_of(Axis(0)));
+                let x_train_sample = x_train.slice_axis(Axis(0), (..n_train).into());
+                let x_test_sample = self.x_test.slice_axis(Axis(0), (..n_test).into());
+                (
+                    self.network.accuracy(
+                        &x_train_sample.to_owned(),
+                        &t_train.slice(s![..n_train, ..]).to_owned(),
+                    ),
+                    self.network.accuracy(
+                        &x_test_sample.to_owned(),
+                        &self.t_test.slice(s![..n_test, ..]).to_owned(),
+                    ),
+                )
+            }
+            None => (
+                self.network.accuracy(x_train, t_train),
+                self.network.accuracy(&self.x_test, &self.t_test),
+            ),
+        };
+        self.history.train_acc_list.push(train_acc);
+        self.history.test_acc_list.push(test_acc);
+
+        if let Some(scheduler) = self.scheduler.as_mut() {
+            scheduler.observe(test_acc);
+            scheduler.step();
+            scheduler.apply(&mut self.optimizer);
+        }
+
+        for callback in &mut self.callbacks {
+            callback.on_epoch_end(self.current_epoch, &self.history);
+        }
+    }
+
+    /// Parameters, optimizer, scheduler and sampler state, counters and history.
+    pub fn checkpoint(&self) -> Checkpoint {
+        Checkpoint {
+            params: self.network.params().clone(),
+            optimizer: self.optimizer.state(),
+            learning_rate: self.optimizer.learning_rate(),
+            scheduler: self
+                .scheduler
+                .as_ref()
+                .map(|scheduler| scheduler.state())
+                .unwrap_or_default(),
+            rng: if self.current_iter.is_multiple_of(self.iter_per_epoch()) {
+                self.train_loader.rng().clone()
+            } else {
+                self.epoch_rng.clone()
+            },
+            iteration: self.current_iter,
+            epoch: self.current_epoch,
+            history: self.history.clone(),
+        }
+    }
+
+    pub fn save_checkpoint(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        self.checkpoint().save(path)
+    }
+
+    /// Continue from a checkpoint saved by a trainer built the same way (model, optimizer,
+    /// scheduler, data, epochs, batch size, sampler and transforms). `train` then picks up at the
+    /// next iteration and produces exactly what the uninterrupted run would have, provided the
+    /// model keeps no state besides its parameters (Dropout masks and BatchNormalization running
+    /// statistics are not saved). Fails without touching the trainer if the parameters, the kind
+    /// of optimizer state or the scheduler state do not match.
+    pub fn resume(&mut self, path: impl AsRef<Path>) -> io::Result<()> {
+        let checkpoint = Checkpoint::load(path)?;
+
+        let expected = self.optimizer.state();
+        if !checkpoint.optimizer.slots.keys().eq(expected.slots.keys())
+            || !checkpoint
+                .optimizer
+                .scalars
+                .keys()
+                .eq(expected.scalars.keys())
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "checkpoint was saved with a different optimizer",
+            ));
+        }
+        let scheduler_state = match self.scheduler.as_mut() {
+            Some(scheduler) => {
+                let previous = scheduler.state();
+                scheduler.load_state(&checkpoint.scheduler)?;
+                Some(previous)
+            }
+            None if checkpoint.scheduler.is_empty() => None,
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "checkpoint was saved with a scheduler",
+                ));
+            }
+        };
+        if let Err(err) = assign_params(self.network.params_mut(), checkpoint.params) {
+            if let (Some(scheduler), Some(previous)) = (self.scheduler.as_mut(), scheduler_state) {
+                scheduler.load_state(&previous)?;
+            }
+            return Err(err);
+        }
+
+        // the slots and scalars were checked above, so this cannot fail
+        self.optimizer.load_state(checkpoint.optimizer)?;
+        self.optimizer.set_learning_rate(checkpoint.learning_rate);
+        self.epoch_rng = checkpoint.rng.clone();
+        self.train_loader.set_rng(checkpoint.rng);
+        self.batches = None;
+        self.current_iter = checkpoint.iteration;
+        self.current_epoch = checkpoint.epoch;
+        self.history = checkpoint.history;
+        Ok(())
+    }
+
+    /// Run the remaining iterations of `epochs` epochs and return the history so far.
+    pub fn train(&mut self) -> TrainingHistory {
+        let max_iter = self.epochs * self.iter_per_epoch();
+        while self.current_iter < max_iter {
+            self.train_step();
+        }
+        self.history.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use ndarray_rand::{RandomExt, rand_distr::StandardNormal};
+
+    use super::*;
+    use crate::{
+        ch04::two_layer::{TwoLayerNet, Weight},
+        ch06::{
+            lr_scheduler::{LinearWarmup, StepDecay},
+            optimizer::{Adam, Sgd},
+            weight_init::WeightInit,
+        },
+        dataset::augment::GaussianNoise,
+    };
+
+    /// `Sgd` that records the learning rate of every update.
+    struct RecordLr {
+        sgd: Sgd,
+        seen: Vec<f64>,
+    }
+
+    impl Optimizer for RecordLr {
+        fn update(
+            &mut self,
+            params: &mut HashMap<String, Weight>,
+            grads: &HashMap<String, Weight>,
+        ) {
+            self.seen.push(self.sgd.lr);
+            self.sgd.update(params, grads);
+        }
+
+        fn learning_rate(&self) -> f64 {
+            self.sgd.learning_rate()
+        }
+
+        fn set_learning_rate(&mut self, lr: f64) {
+            self.sgd.set_learning_rate(lr);
+        }
+    }
+
+    /// 4 inputs, 2 classes, 20 samples.
+    fn data() -> (Array2<f64>, Array2<f64>) {
+        let x = Array::random((20, 4), StandardNormal);
+        let t = Array2::from_shape_fn((20, 2), |(i, j)| (i % 2 == j) as u8 as f64);
+        (x, t)
+    }
+
+    #[test]
+    fn scheduler_sets_the_first_epochs_learning_rate() {
+        let optimizer = RecordLr {
+            sgd: Sgd::new(Some(1.)),
+            seen: Vec::new(),
+        };
+        let mut trainer = Trainer::new(
+            TwoLayerNet::new(4, 3, 2, WeightInit::XavierNormal),
+            optimizer,
+            data(),
+            data(),
+            3,
+            10,
+        )
+        .with_seed(0)
+        .with_scheduler(LinearWarmup::new(StepDecay::new(0.1, 10, None), 2));
+        trainer.train();
+
+        let seen = &trainer.optimizer.seen;
+        let expected = [0.1 / 3., 0.1 / 3., 0.2 / 3., 0.2 / 3., 0.1, 0.1];
+        assert_eq!(seen.len(), expected.len());
+        for (seen, expected) in seen.iter().zip(expected) {
+            assert!((seen - expected).abs() < 1e-12, "{seen} != {expected}");
+        }
+    }
+
+    #[test]
+    fn resume_continues_bit_for_bit() {
+        let network = TwoLayerNet::new(4, 5, 2, WeightInit::HeNormal);
+        let (train, test) = (data(), data());
+        let trainer = |network: TwoLayerNet| {
+            // 20 samples in batches of 6 make 4 iterations per epoch
+            Trainer::new(
+                network,
+                Adam::new(Some(0.01), None, None),
+                train.clone(),
+                test.clone(),
+                3,
+                6,
+            )
+            .with_seed(1)
+            .with_sampler(Sampler::Shuffle)
+            .with_scheduler(StepDecay::new(0.01, 1, Some(0.5)))
+            .with_transform(GaussianNoise::new(0.1))
+            .with_prefetch(2, 2)
+        };
+        let dir = std::env::temp_dir().join("trainer_resume_test");
+        let _ = std::fs::remove_dir_all(&dir);
+        let path = dir.join("run.checkpoint");
+
+        let mut uninterrupted = trainer(network.clone());
+        uninterrupted.train();
+
+        // interrupted in the middle of the second epoch
+        let mut interrupted = trainer(network).with_checkpoint(&path, 5);
+        for _ in 0..6 {
+            interrupted.train_step();
+        }
+        let mut resumed = trainer(TwoLayerNet::new(4, 5, 2, WeightInit::HeNormal));
+        resumed.resume(&path).unwrap();
+        resumed.train();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        for (key, param) in uninterrupted.network.params() {
+            assert_eq!(resumed.network.params()[key].view(), param.view(), "{key}");
+        }
+        let (expected, history) = (uninterrupted.history(), resumed.history());
+        assert_eq!(history.train_loss_list, expected.train_loss_list);
+        assert_eq!(history.train_acc_list, expected.train_acc_list);
+        assert_eq!(history.test_acc_list, expected.test_acc_list);
+        assert_eq!(resumed.optimizer.lr, uninterrupted.optimizer.lr);
+    }
+}
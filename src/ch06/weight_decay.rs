@@ -0,0 +1,104 @@
+use std::collections::HashMap;
+
+use crate::{
+    ch03::mnist_dataset::{MnistDataset, load_mnist},
+    ch04::two_layer::Weight,
+    ch06::{
+        multi_layer_net::{Activation, MultiLayerNet},
+        optimizer::Sgd,
+        trainer::Trainer,
+        weight_init::WeightInit,
+    },
+};
+
+/// L1/L2 penalty on the weights `w*` of a model; biases and other parameters are left alone.
+///
+/// `penalty = l1 * Σ|W| + 0.5 * l2 * Σ W²`, whose gradient is `l1 * sign(W) + l2 * W`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct WeightDecay {
+    pub l1: f64,
+    pub l2: f64,
+}
+
+impl WeightDecay {
+    pub fn new(l1: Option<f64>, l2: Option<f64>) -> Self {
+        Self {
+            l1: l1.unwrap_or(0.),
+            l2: l2.unwrap_or(0.),
+        }
+    }
+
+    /// The book's `weight_decay_lambda`.
+    pub fn l2(lambda: f64) -> Self {
+        Self::new(None, Some(lambda))
+    }
+
+    fn is_weight(key: &str) -> bool {
+        key.starts_with('w')
+    }
+
+    /// Term to add to the data loss.
+    pub fn penalty(&self, params: &HashMap<String, Weight>) -> f64 {
+        params
+            .iter()
+            .filter(|(key, _)| Self::is_weight(key))
+            .map(|(_, w)| {
+                let w = w.view();
+                self.l1 * w.mapv(f64::abs).sum() + 0.5 * self.l2 * w.mapv(|w| w * w).sum()
+            })
+            .sum()
+    }
+
+    /// Add the gradient of `penalty` to `grads`.
+    pub fn add_gradient(
+        &self,
+        params: &HashMap<String, Weight>,
+        grads: &mut HashMap<String, Weight>,
+    ) {
+        for (key, w) in params.iter().filter(|(key, _)| Self::is_weight(key)) {
+            let Some(grad) = grads.get_mut(key) else {
+                continue;
+            };
+            let w = w.view();
+            let mut grad = grad.view_mut();
+            grad.zip_mut_with(&w, |g, &w| {
+                // subgradient 0 at w = 0
+                let sign = if w == 0. { 0. } else { w.signum() };
+                *g += self.l1 * sign + self.l2 * w;
+            });
+        }
+    }
+}
+
+/// Overfitting experiment: a 7-layer network on 300 training samples, with and without
+/// L2 weight decay. The gap between train and test accuracy shrinks with the penalty.
+pub fn run() {
+    let MnistDataset {
+        x_train_2d,
+        t_train,
+        x_test_2d,
+        t_test,
+        ..
+    } = load_mnist((300, 0, 10_000), true, true);
+
+    for weight_decay in [WeightDecay::default(), WeightDecay::l2(0.1)] {
+        println!("{weight_decay:?}");
+        let network =
+            MultiLayerNet::new(784, &[100; 6], 10, Activation::Relu, WeightInit::Std(0.01))
+                .with_weight_decay(weight_decay);
+        let mut trainer = Trainer::new(
+            network,
+            Sgd::new(Some(0.01)),
+            (x_train_2d.clone(), t_train.clone()),
+            (x_test_2d.clone(), t_test.clone()),
+            201,
+            100,
+        );
+        let history = trainer.train();
+        println!(
+            "train acc: {:?} | test acc: {:?}",
+            history.train_acc_list.last().unwrap(),
+            history.test_acc_list.last().unwrap()
+        );
+    }
+}
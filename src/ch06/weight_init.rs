@@ -0,0 +1,50 @@
+use ndarray::{Array, Array2, Dimension, ShapeBuilder};
+use ndarray_rand::{
+    RandomExt,
+    rand_distr::{Normal, Uniform},
+};
+
+/// How to draw the initial weights of a layer from its fan-in (inputs per unit)
+/// and fan-out (outputs per unit).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WeightInit {
+    /// N(0, std²) regardless of the layer size.
+    Std(f64),
+    /// Glorot & Bengio: N(0, 2 / (fan_in + fan_out)). Suits sigmoid and tanh.
+    XavierNormal,
+    /// Glorot & Bengio: U(-a, a) with a = sqrt(6 / (fan_in + fan_out)).
+    XavierUniform,
+    /// Kaiming He: N(0, 2 / fan_in). Suits ReLU.
+    HeNormal,
+    /// Kaiming He: U(-a, a) with a = sqrt(6 / fan_in).
+    HeUniform,
+    /// LeCun: N(0, 1 / fan_in).
+    LeCun,
+}
+
+impl WeightInit {
+    pub fn sample<Sh, D>(&self, shape: Sh, fan_in: usize, fan_out: usize) -> Array<f64, D>
+    where
+        Sh: ShapeBuilder<Dim = D>,
+        D: Dimension,
+    {
+        let fan_in = fan_in as f64;
+        let fan_out = fan_out as f64;
+        let normal = |std: f64| Normal::new(0., std).expect("std must be finite and >= 0");
+        let uniform = |limit: f64| Uniform::new_inclusive(-limit, limit);
+
+        match *self {
+            Self::Std(std) => Array::random(shape, normal(std)),
+            Self::XavierNormal => Array::random(shape, normal((2. / (fan_in + fan_out)).sqrt())),
+            Self::XavierUniform => Array::random(shape, uniform((6. / (fan_in + fan_out)).sqrt())),
+            Self::HeNormal => Array::random(shape, normal((2. / fan_in).sqrt())),
+            Self::HeUniform => Array::random(shape, uniform((6. / fan_in).sqrt())),
+            Self::LeCun => Array::random(shape, normal((1. / fan_in).sqrt())),
+        }
+    }
+
+    /// Weights of an Affine layer mapping `fan_in` inputs to `fan_out` outputs.
+    pub fn dense(&self, fan_in: usize, fan_out: usize) -> Array2<f64> {
+        self.sample((fan_in, fan_out), fan_in, fan_out)
+    }
+}
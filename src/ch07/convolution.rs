@@ -0,0 +1,170 @@
+use std::collections::HashMap;
+
+use ndarray::{Array, Array1, Array2, Array4, Axis, Ix4};
+use ndarray_rand::{RandomExt, rand_distr::StandardNormal};
+
+use crate::{
+    ch03::mnist_dataset::{MnistDataset, load_mnist},
+    ch04::two_layer::Weight,
+    ch05::{
+        gradient_check::{LayerProbe, gradient_check},
+        layers::Layer,
+    },
+    ch06::weight_init::WeightInit,
+    ch07::im2col::{col2im, conv_output_size, im2col},
+};
+
+/// Convolution over (N, C, H, W) inputs with filters `w` (FN, C, FH, FW) and biases `b` (FN).
+/// The output is (N, FN, out_h, out_w). Computed as one matrix product on the `im2col` unfolding.
+#[derive(Clone, Debug)]
+pub struct Convolution {
+    pub w: Array4<f64>,
+    pub b: Array1<f64>,
+    pub stride: usize,
+    pub pad: usize,
+
+    // intermediate data for backward
+    x_shape: Option<(usize, usize, usize, usize)>,
+    col: Option<Array2<f64>>,
+    col_w: Option<Array2<f64>>,
+
+    pub dw: Array4<f64>,
+    pub db: Array1<f64>,
+}
+
+impl Convolution {
+    pub fn new(w: Array4<f64>, b: Array1<f64>, stride: Option<usize>, pad: Option<usize>) -> Self {
+        assert_eq!(w.shape()[0], b.len(), "one bias per filter");
+        let dw = Array4::zeros(w.raw_dim());
+        let db = Array1::zeros(b.raw_dim());
+        Self {
+            w,
+            b,
+            stride: stride.unwrap_or(1),
+            pad: pad.unwrap_or(0),
+            x_shape: None,
+            col: None,
+            col_w: None,
+            dw,
+            db,
+        }
+    }
+
+    /// Filters reshaped to (C * FH * FW, FN), the right operand of the im2col product.
+    fn col_w(&self) -> Array2<f64> {
+        let (fn_, c, fh, fw) = self.w.dim();
+        self.w
+            .to_shape((fn_, c * fh * fw))
+            .unwrap()
+            .t()
+            .into_owned()
+    }
+}
+
+impl Layer<Ix4> for Convolution {
+    fn forward(&mut self, x: &Array4<f64>) -> Array4<f64> {
+        let (fn_, c, fh, fw) = self.w.dim();
+        let (n, x_c, h, w) = x.dim();
+        assert_eq!(x_c, c, "input has {x_c} channels but filters expect {c}");
+        let out_h = conv_output_size(h, fh, self.stride, self.pad);
+        let out_w = conv_output_size(w, fw, self.stride, self.pad);
+
+        let col = im2col(x, fh, fw, self.stride, self.pad);
+        let col_w = self.col_w();
+        let out = col.dot(&col_w) + &self.b;
+        let out = out
+            .into_shape_with_order((n, out_h, out_w, fn_))
+            .unwrap()
+            .permuted_axes([0, 3, 1, 2])
+            .as_standard_layout()
+            .into_owned();
+
+        self.x_shape = Some(x.dim());
+        self.col = Some(col);
+        self.col_w = Some(col_w);
+        out
+    }
+
+    fn backward(&mut self, dout: &Array4<f64>) -> Array4<f64> {
+        let x_shape = self
+            .x_shape
+            .expect("Convolution::backward called before forward");
+        let col = self.col.as_ref().unwrap();
+        let col_w = self.col_w.as_ref().unwrap();
+        let (fn_, c, fh, fw) = self.w.dim();
+
+        let dout = dout
+            .view()
+            .permuted_axes([0, 2, 3, 1])
+            .as_standard_layout()
+            .into_shape_with_order((dout.len() / fn_, fn_))
+            .unwrap()
+            .into_owned();
+
+        self.db = dout.sum_axis(Axis(0));
+        self.dw = col
+            .t()
+            .dot(&dout)
+            .t()
+            .as_standard_layout()
+            .into_shape_with_order((fn_, c, fh, fw))
+            .unwrap()
+            .into_owned();
+
+        let dcol = dout.dot(&col_w.t());
+        col2im(&dcol, x_shape, fh, fw, self.stride, self.pad)
+    }
+
+    fn params(&self) -> HashMap<String, Weight> {
+        HashMap::from([
+            ("w".to_owned(), Weight::M4(self.w.clone())),
+            ("b".to_owned(), Weight::M1(self.b.clone())),
+        ])
+    }
+
+    fn set_params(&mut self, params: &HashMap<String, Weight>) {
+        if let Some(w) = params.get("w") {
+            self.w = w.unwrap_m4();
+        }
+        if let Some(b) = params.get("b") {
+            self.b = b.unwrap_m1();
+        }
+    }
+
+    fn grads(&self) -> HashMap<String, Weight> {
+        HashMap::from([
+            ("w".to_owned(), Weight::M4(self.dw.clone())),
+            ("b".to_owned(), Weight::M1(self.db.clone())),
+        ])
+    }
+}
+
+pub fn run() {
+    let MnistDataset { x_train_3d, .. } = load_mnist((100, 0, 100), true, true);
+    let (n, h, w) = x_train_3d.dim();
+    let x = x_train_3d.into_shape_with_order((n, 1, h, w)).unwrap();
+
+    let weight_init = WeightInit::HeNormal;
+    let mut conv = Convolution::new(
+        weight_init.sample((30, 1, 5, 5), 5 * 5, 30 * 5 * 5),
+        Array1::zeros(30),
+        None,
+        None,
+    );
+    println!("{:?} => {:?}", x.shape(), conv.forward(&x).shape());
+
+    // stride and padding on a small batch
+    let x = Array::random((2, 3, 7, 7), StandardNormal);
+    let conv = Convolution::new(
+        Array::random((4, 3, 3, 3), StandardNormal),
+        Array::random(4, StandardNormal),
+        Some(2),
+        Some(1),
+    );
+    let dout = Array::random((2, 4 * 4 * 4), StandardNormal);
+    let mut probe = LayerProbe::new(conv, x);
+    println!(
+        "Convolution\n{}",
+        gradient_check(&mut probe, &(), &dout, 1e-5)
+    );
+}
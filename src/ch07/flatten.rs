@@ -0,0 +1,27 @@
+use ndarray::{Array2, Array4};
+
+/// Reshape (N, C, H, W) to (N, C * H * W) so convolutional layers can feed `Affine`.
+/// Changes the rank, so it has its own forward/backward instead of implementing `Layer`.
+#[derive(Clone, Debug, Default)]
+pub struct Flatten {
+    x_shape: Option<(usize, usize, usize, usize)>,
+}
+
+impl Flatten {
+    pub fn new() -> Self {
+        Self { x_shape: None }
+    }
+
+    pub fn forward(&mut self, x: &Array4<f64>) -> Array2<f64> {
+        let (n, c, h, w) = x.dim();
+        self.x_shape = Some(x.dim());
+        x.to_shape((n, c * h * w)).unwrap().into_owned()
+    }
+
+    pub fn backward(&mut self, dout: &Array2<f64>) -> Array4<f64> {
+        let x_shape = self
+            .x_shape
+            .expect("Flatten::backward called before forward");
+        dout.to_shape(x_shape).unwrap().into_owned()
+    }
+}
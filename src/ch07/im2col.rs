@@ -0,0 +1,81 @@
+use ndarray::{Array2, Array4, Array6, s};
+
+/// Spatial size of a convolution or pooling output along one axis.
+pub fn conv_output_size(input_size: usize, filter_size: usize, stride: usize, pad: usize) -> usize {
+    assert!(stride > 0, "stride must be positive");
+    assert!(
+        input_size + 2 * pad >= filter_size,
+        "filter of size {filter_size} does not fit input of size {input_size} with pad {pad}"
+    );
+    (input_size + 2 * pad - filter_size) / stride + 1
+}
+
+/// Unfold every receptive field of `input_data` (N, C, H, W) into a row.
+///
+/// Returns (N * out_h * out_w, C * filter_h * filter_w). Rows are ordered by (n, y, x) and
+/// columns by (c, fy, fx), so a filter reshaped to (FN, C * FH * FW) lines up with the columns.
+pub fn im2col(
+    input_data: &Array4<f64>,
+    filter_h: usize,
+    filter_w: usize,
+    stride: usize,
+    pad: usize,
+) -> Array2<f64> {
+    let (n, c, h, w) = input_data.dim();
+    let out_h = conv_output_size(h, filter_h, stride, pad);
+    let out_w = conv_output_size(w, filter_w, stride, pad);
+
+    let mut img = Array4::zeros((n, c, h + 2 * pad, w + 2 * pad));
+    img.slice_mut(s![.., .., pad..pad + h, pad..pad + w])
+        .assign(input_data);
+
+    let mut col = Array6::zeros((n, c, filter_h, filter_w, out_h, out_w));
+    for y in 0..filter_h {
+        let y_max = y + stride * (out_h - 1) + 1;
+        for x in 0..filter_w {
+            let x_max = x + stride * (out_w - 1) + 1;
+            col.slice_mut(s![.., .., y, x, .., ..])
+                .assign(&img.slice(s![.., .., y..y_max;stride, x..x_max;stride]));
+        }
+    }
+
+    col.permuted_axes([0, 4, 5, 1, 2, 3])
+        .as_standard_layout()
+        .into_shape_with_order((n * out_h * out_w, c * filter_h * filter_w))
+        .unwrap()
+        .into_owned()
+}
+
+/// Inverse layout of `im2col`: fold the rows of `col` back into an (N, C, H, W) array of
+/// `input_shape`. Overlapping receptive fields are summed, which is what backward needs.
+pub fn col2im(
+    col: &Array2<f64>,
+    input_shape: (usize, usize, usize, usize),
+    filter_h: usize,
+    filter_w: usize,
+    stride: usize,
+    pad: usize,
+) -> Array4<f64> {
+    let (n, c, h, w) = input_shape;
+    let out_h = conv_output_size(h, filter_h, stride, pad);
+    let out_w = conv_output_size(w, filter_w, stride, pad);
+
+    let col = col
+        .as_standard_layout()
+        .into_shape_with_order((n, out_h, out_w, c, filter_h, filter_w))
+        .expect("col does not match input_shape and filter size")
+        .permuted_axes([0, 3, 4, 5, 1, 2]);
+
+    let mut img = Array4::zeros((n, c, h + 2 * pad, w + 2 * pad));
+    for y in 0..filter_h {
+        let y_max = y + stride * (out_h - 1) + 1;
+        for x in 0..filter_w {
+            let x_max = x + stride * (out_w - 1) + 1;
+            let mut window = img.slice_mut(s![.., .., y..y_max;stride, x..x_max;stride]);
+            window += &col.slice(s![.., .., y, x, .., ..]);
+        }
+    }
+
+    img.slice(s![.., .., pad..pad + h, pad..pad + w])
+        .into_owned()
+}
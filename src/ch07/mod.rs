@@ -0,0 +1,5 @@
+pub mod convolution;
+pub mod flatten;
+pub mod im2col;
+pub mod pooling;
+pub mod simple_convnet;
@@ -0,0 +1,214 @@
+use ndarray::{Array, Array1, Array2, Array4, Axis, Ix4, s};
+use ndarray_rand::{RandomExt, rand_distr::StandardNormal};
+use ndarray_stats::QuantileExt;
+
+use crate::{
+    ch05::{
+        gradient_check::{LayerProbe, gradient_check},
+        layers::Layer,
+    },
+    ch07::im2col::{col2im, conv_output_size, im2col},
+};
+
+type Shape4 = (usize, usize, usize, usize);
+
+/// Every pooling window of `x` as a row: (N * out_h * out_w * C, pool_h * pool_w).
+/// The border is filled with `pad_value`.
+fn pool_windows(
+    x: &Array4<f64>,
+    (pool_h, pool_w): (usize, usize),
+    stride: usize,
+    pad: usize,
+    pad_value: f64,
+) -> Array2<f64> {
+    let (n, c, h, w) = x.dim();
+    let mut padded = Array4::from_elem((n, c, h + 2 * pad, w + 2 * pad), pad_value);
+    padded
+        .slice_mut(s![.., .., pad..pad + h, pad..pad + w])
+        .assign(x);
+
+    let col = im2col(&padded, pool_h, pool_w, stride, 0);
+    let rows = col.len() / (pool_h * pool_w);
+    col.into_shape_with_order((rows, pool_h * pool_w)).unwrap()
+}
+
+/// Inverse of `pool_windows`: sum the per-window gradients back into an array of `x_shape`.
+fn fold_windows(
+    dwindows: Array2<f64>,
+    (n, c, h, w): Shape4,
+    (pool_h, pool_w): (usize, usize),
+    stride: usize,
+    pad: usize,
+) -> Array4<f64> {
+    let rows = dwindows.len() / (c * pool_h * pool_w);
+    let dcol = dwindows
+        .into_shape_with_order((rows, c * pool_h * pool_w))
+        .unwrap();
+    let dx = col2im(
+        &dcol,
+        (n, c, h + 2 * pad, w + 2 * pad),
+        pool_h,
+        pool_w,
+        stride,
+        0,
+    );
+    dx.slice(s![.., .., pad..pad + h, pad..pad + w])
+        .into_owned()
+}
+
+/// One value per window (in `pool_windows` row order) -> (N, C, out_h, out_w).
+fn windows_to_output(
+    pooled: Array1<f64>,
+    (n, c, h, w): Shape4,
+    pool: (usize, usize),
+    stride: usize,
+    pad: usize,
+) -> Array4<f64> {
+    let out_h = conv_output_size(h, pool.0, stride, pad);
+    let out_w = conv_output_size(w, pool.1, stride, pad);
+    pooled
+        .into_shape_with_order((n, out_h, out_w, c))
+        .unwrap()
+        .permuted_axes([0, 3, 1, 2])
+        .as_standard_layout()
+        .into_owned()
+}
+
+/// (N, C, out_h, out_w) -> one value per window, in `pool_windows` row order.
+fn output_to_windows(dout: &Array4<f64>) -> Array1<f64> {
+    dout.view()
+        .permuted_axes([0, 2, 3, 1])
+        .iter()
+        .copied()
+        .collect()
+}
+
+/// Max pooling over (N, C, H, W). Padding never wins the max, and backward routes each
+/// gradient to the position remembered in `arg_max`.
+#[derive(Clone, Debug)]
+pub struct MaxPooling {
+    pub pool_h: usize,
+    pub pool_w: usize,
+    pub stride: usize,
+    pub pad: usize,
+
+    // intermediate data for backward
+    x_shape: Option<Shape4>,
+    arg_max: Option<Array1<usize>>,
+}
+
+impl MaxPooling {
+    pub fn new(pool_h: usize, pool_w: usize, stride: Option<usize>, pad: Option<usize>) -> Self {
+        let pad = pad.unwrap_or(0);
+        assert!(
+            pad < pool_h && pad < pool_w,
+            "pad must be smaller than the pool window"
+        );
+        Self {
+            pool_h,
+            pool_w,
+            stride: stride.unwrap_or(1),
+            pad,
+            x_shape: None,
+            arg_max: None,
+        }
+    }
+}
+
+impl Layer<Ix4> for MaxPooling {
+    fn forward(&mut self, x: &Array4<f64>) -> Array4<f64> {
+        let pool = (self.pool_h, self.pool_w);
+        let windows = pool_windows(x, pool, self.stride, self.pad, f64::NEG_INFINITY);
+        let arg_max = windows.map_axis(Axis(1), |w| w.argmax().unwrap());
+        let out = windows.map_axis(Axis(1), |w| *w.max().unwrap());
+
+        self.x_shape = Some(x.dim());
+        self.arg_max = Some(arg_max);
+        windows_to_output(out, x.dim(), pool, self.stride, self.pad)
+    }
+
+    fn backward(&mut self, dout: &Array4<f64>) -> Array4<f64> {
+        let x_shape = self
+            .x_shape
+            .expect("MaxPooling::backward called before forward");
+        let arg_max = self.arg_max.as_ref().unwrap();
+        let pool = (self.pool_h, self.pool_w);
+
+        let dout = output_to_windows(dout);
+        let mut dmax = Array2::zeros((dout.len(), self.pool_h * self.pool_w));
+        for (i, (&d, &idx)) in dout.iter().zip(arg_max).enumerate() {
+            dmax[[i, idx]] = d;
+        }
+        fold_windows(dmax, x_shape, pool, self.stride, self.pad)
+    }
+}
+
+/// Average pooling over (N, C, H, W). Padded zeros count towards the average.
+#[derive(Clone, Debug)]
+pub struct AvgPooling {
+    pub pool_h: usize,
+    pub pool_w: usize,
+    pub stride: usize,
+    pub pad: usize,
+
+    x_shape: Option<Shape4>,
+}
+
+impl AvgPooling {
+    pub fn new(pool_h: usize, pool_w: usize, stride: Option<usize>, pad: Option<usize>) -> Self {
+        Self {
+            pool_h,
+            pool_w,
+            stride: stride.unwrap_or(1),
+            pad: pad.unwrap_or(0),
+            x_shape: None,
+        }
+    }
+}
+
+impl Layer<Ix4> for AvgPooling {
+    fn forward(&mut self, x: &Array4<f64>) -> Array4<f64> {
+        let pool = (self.pool_h, self.pool_w);
+        let windows = pool_windows(x, pool, self.stride, self.pad, 0.);
+        let out = windows.mean_axis(Axis(1)).unwrap();
+
+        self.x_shape = Some(x.dim());
+        windows_to_output(out, x.dim(), pool, self.stride, self.pad)
+    }
+
+    fn backward(&mut self, dout: &Array4<f64>) -> Array4<f64> {
+        let x_shape = self
+            .x_shape
+            .expect("AvgPooling::backward called before forward");
+        let pool = (self.pool_h, self.pool_w);
+        let pool_size = self.pool_h * self.pool_w;
+
+        let dout = output_to_windows(dout) / pool_size as f64;
+        let rows = dout.len();
+        let davg = dout
+            .insert_axis(Axis(1))
+            .broadcast((rows, pool_size))
+            .unwrap()
+            .into_owned();
+        fold_windows(davg, x_shape, pool, self.stride, self.pad)
+    }
+}
+
+pub fn run() {
+    let threshold = 1e-5;
+    let x = Array::random((2, 3, 6, 5), StandardNormal);
+
+    let mut max_pooling = LayerProbe::new(MaxPooling::new(2, 2, Some(2), Some(1)), x.clone());
+    let dout = Array::random((2, 3 * 4 * 3), StandardNormal);
+    println!(
+        "MaxPooling\n{}",
+        gradient_check(&mut max_pooling, &(), &dout, threshold)
+    );
+
+    let mut avg_pooling = LayerProbe::new(AvgPooling::new(3, 2, Some(1), Some(1)), x);
+    let dout = Array::random((2, 3 * 6 * 6), StandardNormal);
+    println!(
+        "AvgPooling\n{}",
+        gradient_check(&mut avg_pooling, &(), &dout, threshold)
+    );
+}
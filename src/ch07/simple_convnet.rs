@@ -0,0 +1,223 @@
+use std::collections::HashMap;
+
+use ndarray::{Array1, Array2, Array3, Array4, Axis, Ix4};
+
+use crate::{
+    ch03::mnist_dataset::{MnistDataset, load_mnist},
+    ch04::two_layer::Weight,
+    ch05::{
+        layers::{Affine, Layer, Relu, SoftmaxWithLoss},
+        model::{Model, batched_accuracy},
+    },
+    ch06::{
+        optimizer::Adam,
+        trainer::{PrintProgress, Trainer},
+        weight_init::WeightInit,
+    },
+    ch07::{
+        convolution::Convolution, flatten::Flatten, im2col::conv_output_size, pooling::MaxPooling,
+    },
+};
+
+/// (N, H, W) grayscale images -> (N, 1, H, W), the layout convolutional layers expect.
+pub fn to_nchw(x: Array3<f64>) -> Array4<f64> {
+    x.insert_axis(Axis(1))
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ConvParam {
+    pub filter_num: usize,
+    pub filter_size: usize,
+    pub pad: usize,
+    pub stride: usize,
+}
+
+impl Default for ConvParam {
+    fn default() -> Self {
+        Self {
+            filter_num: 30,
+            filter_size: 5,
+            pad: 0,
+            stride: 1,
+        }
+    }
+}
+
+/// conv - relu - pool - affine - relu - affine - softmax
+///
+/// Parameters: `w1`/`b1` convolution filters (FN, C, FS, FS) and biases, `w2`/`b2` and `w3`/`b3`
+/// the two Affine layers.
+pub struct SimpleConvNet {
+    params: HashMap<String, Weight>,
+    conv1: Convolution,
+    relu1: Relu<Ix4>,
+    pool1: MaxPooling,
+    flatten: Flatten,
+    affine1: Affine,
+    relu2: Relu,
+    affine2: Affine,
+    last_layer: SoftmaxWithLoss,
+}
+
+impl SimpleConvNet {
+    pub fn new(
+        (input_channels, input_h, input_w): (usize, usize, usize),
+        conv_param: ConvParam,
+        hidden_size: usize,
+        output_size: usize,
+        weight_init: WeightInit,
+    ) -> Self {
+        let ConvParam {
+            filter_num,
+            filter_size,
+            pad,
+            stride,
+        } = conv_param;
+        let conv_output_h = conv_output_size(input_h, filter_size, stride, pad);
+        let conv_output_w = conv_output_size(input_w, filter_size, stride, pad);
+        let pool_output_size = filter_num * (conv_output_h / 2) * (conv_output_w / 2);
+
+        let w1 = weight_init.sample(
+            (filter_num, input_channels, filter_size, filter_size),
+            input_channels * filter_size * filter_size,
+            filter_num * filter_size * filter_size,
+        );
+        let b1 = Array1::zeros(filter_num);
+        let w2 = weight_init.dense(pool_output_size, hidden_size);
+        let b2 = Array1::zeros(hidden_size);
+        let w3 = weight_init.dense(hidden_size, output_size);
+        let b3 = Array1::zeros(output_size);
+
+        let conv1 = Convolution::new(w1.clone(), b1.clone(), Some(stride), Some(pad));
+        let affine1 = Affine::new(w2.clone(), b2.clone());
+        let affine2 = Affine::new(w3.clone(), b3.clone());
+
+        let mut params = HashMap::new();
+        params.insert("w1".to_owned(), Weight::M4(w1));
+        params.insert("b1".to_owned(), Weight::M1(b1));
+        params.insert("w2".to_owned(), Weight::M2(w2));
+        params.insert("b2".to_owned(), Weight::M1(b2));
+        params.insert("w3".to_owned(), Weight::M2(w3));
+        params.insert("b3".to_owned(), Weight::M1(b3));
+
+        Self {
+            params,
+            conv1,
+            relu1: Relu::new(),
+            pool1: MaxPooling::new(2, 2, Some(2), None),
+            flatten: Flatten::new(),
+            affine1,
+            relu2: Relu::new(),
+            affine2,
+            last_layer: SoftmaxWithLoss::new(),
+        }
+    }
+
+    /// Push the current parameters into the layers.
+    fn sync_params(&mut self) {
+        let block = |idx: usize| {
+            HashMap::from([
+                ("w".to_owned(), self.params[&format!("w{idx}")].clone()),
+                ("b".to_owned(), self.params[&format!("b{idx}")].clone()),
+            ])
+        };
+        self.conv1.set_params(&block(1));
+        self.affine1.set_params(&block(2));
+        self.affine2.set_params(&block(3));
+    }
+}
+
+impl Model for SimpleConvNet {
+    type Input = Array4<f64>;
+
+    fn params(&self) -> &HashMap<String, Weight> {
+        &self.params
+    }
+
+    fn params_mut(&mut self) -> &mut HashMap<String, Weight> {
+        &mut self.params
+    }
+
+    fn predict(&mut self, x: &Array4<f64>) -> Array2<f64> {
+        self.sync_params();
+        let x = self.conv1.forward(x);
+        let x = self.relu1.forward(&x);
+        let x = self.pool1.forward(&x);
+        let x = self.flatten.forward(&x);
+        let x = self.affine1.forward(&x);
+        let x = self.relu2.forward(&x);
+        self.affine2.forward(&x)
+    }
+
+    fn loss(&mut self, x: &Array4<f64>, t: &Array2<f64>) -> f64 {
+        let y = self.predict(x);
+        self.last_layer.forward(&y, t)
+    }
+
+    /// Evaluated in batches of 100 to bound the size of the im2col buffers.
+    fn accuracy(&mut self, x: &Array4<f64>, t: &Array2<f64>) -> f64 {
+        batched_accuracy(self, x, t, 100)
+    }
+
+    fn gradient(&mut self, x: &Array4<f64>, t: &Array2<f64>) -> HashMap<String, Weight> {
+        // forward
+        self.loss(x, t);
+
+        // backward
+        let dout = self.last_layer.backward(1.);
+        let dout = self.affine2.backward(&dout);
+        let dout = self.relu2.backward(&dout);
+        let dout = self.affine1.backward(&dout);
+        let dout = self.flatten.backward(&dout);
+        let dout = self.pool1.backward(&dout);
+        let dout = self.relu1.backward(&dout);
+        self.conv1.backward(&dout);
+
+        let mut grads = HashMap::new();
+        grads.insert("w1".to_owned(), Weight::M4(self.conv1.dw.clone()));
+        grads.insert("b1".to_owned(), Weight::M1(self.conv1.db.clone()));
+        grads.insert("w2".to_owned(), Weight::M2(self.affine1.dw.clone()));
+        grads.insert("b2".to_owned(), Weight::M1(self.affine1.db.clone()));
+        grads.insert("w3".to_owned(), Weight::M2(self.affine2.dw.clone()));
+        grads.insert("b3".to_owned(), Weight::M1(self.affine2.db.clone()));
+
+        grads
+    }
+}
+
+pub fn run() {
+    let MnistDataset {
+        x_train_3d,
+        t_train,
+        x_test_3d,
+        t_test,
+        ..
+    } = load_mnist((60_000, 0, 10_000), true, true);
+
+    let network = SimpleConvNet::new(
+        (1, 28, 28),
+        ConvParam::default(),
+        100,
+        10,
+        WeightInit::Std(0.01),
+    );
+    let x_test = to_nchw(x_test_3d);
+    let mut trainer = Trainer::new(
+        network,
+        Adam::new(Some(0.001), None, None),
+        (to_nchw(x_train_3d), t_train),
+        (x_test.clone(), t_test.clone()),
+        20,
+        100,
+    )
+    .with_evaluate_sample_num(1000)
+    .with_callback(PrintProgress {
+        loss_interval: Some(100),
+    });
+    trainer.train();
+
+    trainer.network.eval();
+    let accuracy = trainer.network.accuracy(&x_test, &t_test);
+    println!("=============== Final Test Accuracy ===============");
+    println!("test acc: {accuracy}");
+}
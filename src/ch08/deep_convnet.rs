@@ -0,0 +1,303 @@
+use std::collections::HashMap;
+
+use ndarray::{Array1, Array2, Array4, Ix4};
+
+use crate::{
+    ch03::mnist_dataset::{MnistDataset, load_mnist},
+    ch04::two_layer::Weight,
+    ch05::{
+        layers::{Affine, Layer, Relu, SoftmaxWithLoss},
+        model::{Model, batched_accuracy},
+    },
+    ch06::{
+        dropout::Dropout,
+        multi_layer_net::split_key,
+        optimizer::Adam,
+        trainer::{PrintProgress, Trainer},
+        weight_init::WeightInit,
+    },
+    ch07::{
+        convolution::Convolution,
+        flatten::Flatten,
+        im2col::conv_output_size,
+        pooling::MaxPooling,
+        simple_convnet::{ConvParam, to_nchw},
+    },
+};
+
+/// Filters of the six 3x3 convolutions. A 2x2 max pooling follows every second one.
+const CONV_PARAMS: [ConvParam; 6] = [
+    conv3x3(16, 1),
+    conv3x3(16, 1),
+    conv3x3(32, 1),
+    conv3x3(32, 2),
+    conv3x3(64, 1),
+    conv3x3(64, 1),
+];
+
+const fn conv3x3(filter_num: usize, pad: usize) -> ConvParam {
+    ConvParam {
+        filter_num,
+        filter_size: 3,
+        pad,
+        stride: 1,
+    }
+}
+
+/// VGG-style network reaching over 99% on MNIST:
+///
+/// (conv - relu - conv - relu - pool) x 3 - affine - relu - dropout - affine - dropout - softmax
+///
+/// Parameters: `w1..w6`/`b1..b6` for the convolutions, `w7`/`b7` and `w8`/`b8` for the
+/// Affine layers. A new network is in inference mode.
+pub struct DeepConvNet {
+    params: HashMap<String, Weight>,
+    /// Every layer is tagged with its block number, as in `MultiLayerNet`.
+    conv_layers: Vec<(usize, Box<dyn Layer<Ix4>>)>,
+    flatten: Flatten,
+    dense_layers: Vec<(usize, Box<dyn Layer>)>,
+    last_layer: SoftmaxWithLoss,
+    train_flg: bool,
+    dropout: (f64, Option<u64>),
+}
+
+impl DeepConvNet {
+    pub fn new(
+        (input_channels, input_h, input_w): (usize, usize, usize),
+        hidden_size: usize,
+        output_size: usize,
+        weight_init: WeightInit,
+    ) -> Self {
+        let mut params = HashMap::new();
+        let (mut c, mut h, mut w) = (input_channels, input_h, input_w);
+        for (idx, conv_param) in CONV_PARAMS.iter().enumerate() {
+            let ConvParam {
+                filter_num,
+                filter_size,
+                pad,
+                stride,
+            } = *conv_param;
+            let filter = weight_init.sample(
+                (filter_num, c, filter_size, filter_size),
+                c * filter_size * filter_size,
+                filter_num * filter_size * filter_size,
+            );
+            params.insert(format!("w{}", idx + 1), Weight::M4(filter));
+            params.insert(
+                format!("b{}", idx + 1),
+                Weight::M1(Array1::zeros(filter_num)),
+            );
+
+            c = filter_num;
+            h = conv_output_size(h, filter_size, stride, pad);
+            w = conv_output_size(w, filter_size, stride, pad);
+            if !idx.is_multiple_of(2) {
+                h = conv_output_size(h, 2, 2, 0);
+                w = conv_output_size(w, 2, 2, 0);
+            }
+        }
+
+        let flat_size = c * h * w;
+        params.insert(
+            "w7".to_owned(),
+            Weight::M2(weight_init.dense(flat_size, hidden_size)),
+        );
+        params.insert("b7".to_owned(), Weight::M1(Array1::zeros(hidden_size)));
+        params.insert(
+            "w8".to_owned(),
+            Weight::M2(weight_init.dense(hidden_size, output_size)),
+        );
+        params.insert("b8".to_owned(), Weight::M1(Array1::zeros(output_size)));
+
+        let mut network = Self {
+            params,
+            conv_layers: Vec::new(),
+            flatten: Flatten::new(),
+            dense_layers: Vec::new(),
+            last_layer: SoftmaxWithLoss::new(),
+            train_flg: false,
+            dropout: (0.5, None),
+        };
+        network.build_layers();
+        network
+    }
+
+    /// Replace the default dropout ratio of 0.5. With a seed, the layer of block `i` is
+    /// seeded with `seed + i`.
+    pub fn with_dropout(mut self, dropout_ratio: f64, seed: Option<u64>) -> Self {
+        self.dropout = (dropout_ratio, seed);
+        self.build_layers();
+        self
+    }
+
+    pub fn train_flg(&self) -> bool {
+        self.train_flg
+    }
+
+    fn build_layers(&mut self) {
+        let mut conv_layers: Vec<(usize, Box<dyn Layer<Ix4>>)> = Vec::new();
+        for (idx, conv_param) in (1..).zip(CONV_PARAMS) {
+            let conv = Convolution::new(
+                self.params[&format!("w{idx}")].unwrap_m4(),
+                self.params[&format!("b{idx}")].unwrap_m1(),
+                Some(conv_param.stride),
+                Some(conv_param.pad),
+            );
+            conv_layers.push((idx, Box::new(conv)));
+            conv_layers.push((idx, Box::new(Relu::<Ix4>::new())));
+            if idx.is_multiple_of(2) {
+                conv_layers.push((idx, Box::new(MaxPooling::new(2, 2, Some(2), None))));
+            }
+        }
+
+        let (dropout_ratio, seed) = self.dropout;
+        let affine = |idx: usize| {
+            Affine::new(
+                self.params[&format!("w{idx}")].unwrap_m2(),
+                self.params[&format!("b{idx}")].unwrap_m1(),
+            )
+        };
+        let dropout =
+            |idx: usize| Dropout::new(Some(dropout_ratio), seed.map(|seed| seed + idx as u64));
+        let dense_layers: Vec<(usize, Box<dyn Layer>)> = vec![
+            (7, Box::new(affine(7))),
+            (7, Box::new(Relu::new())),
+            (7, Box::new(dropout(7))),
+            (8, Box::new(affine(8))),
+            (8, Box::new(dropout(8))),
+        ];
+
+        self.conv_layers = conv_layers;
+        self.dense_layers = dense_layers;
+    }
+
+    /// Push the current parameters and mode into the layers.
+    fn sync_params(&mut self) {
+        let mut blocks: HashMap<usize, HashMap<String, Weight>> = HashMap::new();
+        for (key, param) in &self.params {
+            let (name, idx) = split_key(key);
+            blocks
+                .entry(idx)
+                .or_default()
+                .insert(name.to_owned(), param.clone());
+        }
+
+        for (idx, layer) in self.conv_layers.iter_mut() {
+            if let Some(block) = blocks.get(idx) {
+                layer.set_params(block);
+            }
+        }
+        for (idx, layer) in self.dense_layers.iter_mut() {
+            if let Some(block) = blocks.get(idx) {
+                layer.set_params(block);
+            }
+            layer.set_train_flg(self.train_flg);
+        }
+    }
+}
+
+impl Model for DeepConvNet {
+    type Input = Array4<f64>;
+
+    fn params(&self) -> &HashMap<String, Weight> {
+        &self.params
+    }
+
+    fn params_mut(&mut self) -> &mut HashMap<String, Weight> {
+        &mut self.params
+    }
+
+    fn set_train_flg(&mut self, train_flg: bool) {
+        self.train_flg = train_flg;
+    }
+
+    fn predict(&mut self, x: &Array4<f64>) -> Array2<f64> {
+        self.sync_params();
+        let mut x = x.clone();
+        for (_, layer) in self.conv_layers.iter_mut() {
+            x = layer.forward(&x);
+        }
+        let mut x = self.flatten.forward(&x);
+        for (_, layer) in self.dense_layers.iter_mut() {
+            x = layer.forward(&x);
+        }
+        x
+    }
+
+    fn loss(&mut self, x: &Array4<f64>, t: &Array2<f64>) -> f64 {
+        let y = self.predict(x);
+        self.last_layer.forward(&y, t)
+    }
+
+    /// Evaluated in batches of 100 to bound the size of the im2col buffers.
+    fn accuracy(&mut self, x: &Array4<f64>, t: &Array2<f64>) -> f64 {
+        batched_accuracy(self, x, t, 100)
+    }
+
+    /// Always differentiates the training-mode forward pass, whatever `train_flg` is.
+    fn gradient(&mut self, x: &Array4<f64>, t: &Array2<f64>) -> HashMap<String, Weight> {
+        // forward
+        let train_flg = self.train_flg;
+        self.train_flg = true;
+        self.loss(x, t);
+        self.train_flg = train_flg;
+
+        // backward
+        let mut dout = self.last_layer.backward(1.);
+        for (_, layer) in self.dense_layers.iter_mut().rev() {
+            dout = layer.backward(&dout);
+        }
+        let mut dout = self.flatten.backward(&dout);
+        for (_, layer) in self.conv_layers.iter_mut().rev() {
+            dout = layer.backward(&dout);
+        }
+
+        let mut grads = HashMap::new();
+        let conv_grads = self
+            .conv_layers
+            .iter()
+            .map(|(idx, layer)| (idx, layer.grads()));
+        let dense_grads = self
+            .dense_layers
+            .iter()
+            .map(|(idx, layer)| (idx, layer.grads()));
+        for (idx, layer_grads) in conv_grads.chain(dense_grads) {
+            for (name, grad) in layer_grads {
+                grads.insert(format!("{name}{idx}"), grad);
+            }
+        }
+
+        grads
+    }
+}
+
+pub fn run() {
+    let MnistDataset {
+        x_train_3d,
+        t_train,
+        x_test_3d,
+        t_test,
+        ..
+    } = load_mnist((60_000, 0, 10_000), true, true);
+
+    let network = DeepConvNet::new((1, 28, 28), 50, 10, WeightInit::HeNormal);
+    let x_test = to_nchw(x_test_3d);
+    let mut trainer = Trainer::new(
+        network,
+        Adam::new(Some(0.001), None, None),
+        (to_nchw(x_train_3d), t_train),
+        (x_test.clone(), t_test.clone()),
+        20,
+        100,
+    )
+    .with_evaluate_sample_num(1000)
+    .with_callback(PrintProgress {
+        loss_interval: Some(100),
+    });
+    trainer.train();
+
+    trainer.network.eval();
+    let accuracy = trainer.network.accuracy(&x_test, &t_test);
+    println!("=============== Final Test Accuracy ===============");
+    println!("test acc: {accuracy}");
+}
@@ -0,0 +1 @@
+pub mod deep_convnet;
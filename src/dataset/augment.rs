@@ -0,0 +1,439 @@
+//! Random image augmentations, applied on the fly with `DataLoader::with_transform`.
+//!
+//! Every transform takes batches shaped (N, ..., H, W): the (N, 28, 28) of `x_train_3d`,
+//! (N, 1, 28, 28) after `to_nchw`, or (N, 3, 32, 32) for CIFAR. Each image draws its own random
+//! parameters and shares them across its channels. Geometric transforms interpolate
+//! bilinearly and repeat the border pixels where they would sample outside the image, so the
+//! background stays as it is whatever the normalization.
+
+use std::f64::consts::PI;
+
+use ndarray::{Array, Array2, Array4, ArrayView2, ArrayViewMut3, Axis, Dimension, Ix3, s};
+use ndarray_rand::{
+    rand::{Rng, SeedableRng},
+    rand_distr::{StandardNormal, Uniform},
+};
+use rand_chacha::ChaCha8Rng;
+
+use crate::{
+    ch03::mnist_dataset::{ImageLayout, MnistLoader},
+    dataset::transform::Transform,
+};
+
+/// Call `f` on every image of a (N, ..., H, W) batch as a (C, H, W) view, where C multiplies
+/// the axes between N and H.
+fn map_images<D: Dimension>(
+    x: Array<f64, D>,
+    mut f: impl FnMut(ArrayViewMut3<f64>),
+) -> Array<f64, D> {
+    let dim = x.raw_dim();
+    let shape = x.shape();
+    let ndim = shape.len();
+    assert!(ndim >= 3, "expected a batch of images, got shape {shape:?}");
+    let (n, h, w) = (shape[0], shape[ndim - 2], shape[ndim - 1]);
+    let c = shape[1..ndim - 2].iter().product();
+
+    let x = if x.is_standard_layout() {
+        x
+    } else {
+        x.as_standard_layout().into_owned()
+    };
+    let mut images: Array4<f64> = x.into_shape_with_order((n, c, h, w)).unwrap();
+    for image in images.outer_iter_mut() {
+        f(image);
+    }
+    images.into_shape_with_order(dim).unwrap()
+}
+
+/// Value of `plane` at the fractional position (`y`, `x`), clamped into the image.
+fn bilinear(plane: ArrayView2<f64>, y: f64, x: f64) -> f64 {
+    let (h, w) = plane.dim();
+    let y = y.clamp(0., (h - 1) as f64);
+    let x = x.clamp(0., (w - 1) as f64);
+    let (y0, x0) = (y.floor() as usize, x.floor() as usize);
+    let (y1, x1) = ((y0 + 1).min(h - 1), (x0 + 1).min(w - 1));
+    let (dy, dx) = (y - y0 as f64, x - x0 as f64);
+    plane[[y0, x0]] * (1. - dy) * (1. - dx)
+        + plane[[y0, x1]] * (1. - dy) * dx
+        + plane[[y1, x0]] * dy * (1. - dx)
+        + plane[[y1, x1]] * dy * dx
+}
+
+/// Set every pixel (y, x) of every channel to the original image at `source(y, x)`.
+fn warp(mut image: ArrayViewMut3<f64>, source: impl Fn(usize, usize) -> (f64, f64)) {
+    let original = image.to_owned();
+    let (_, h, w) = image.dim();
+    for y in 0..h {
+        for x in 0..w {
+            let (sy, sx) = source(y, x);
+            for (c, plane) in original.outer_iter().enumerate() {
+                image[[c, y, x]] = bilinear(plane, sy, sx);
+            }
+        }
+    }
+}
+
+/// Center of `image` in pixel coordinates.
+fn center(image: &ArrayViewMut3<f64>) -> (f64, f64) {
+    let (_, h, w) = image.dim();
+    ((h - 1) as f64 / 2., (w - 1) as f64 / 2.)
+}
+
+/// Translate by a whole number of pixels, up to `max_shift` along each axis.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RandomShift {
+    max_shift: usize,
+}
+
+impl RandomShift {
+    pub fn new(max_shift: usize) -> Self {
+        Self { max_shift }
+    }
+}
+
+impl<D: Dimension> Transform<D> for RandomShift {
+    fn apply(&self, x: Array<f64, D>, rng: &mut ChaCha8Rng) -> Array<f64, D> {
+        let max = self.max_shift as i64;
+        map_images(x, |image| {
+            let dy = rng.gen_range(-max..=max) as f64;
+            let dx = rng.gen_range(-max..=max) as f64;
+            warp(image, |y, x| (y as f64 - dy, x as f64 - dx));
+        })
+    }
+}
+
+/// Rotate about the center by up to `max_degrees` either way.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RandomRotation {
+    max_degrees: f64,
+}
+
+impl RandomRotation {
+    pub fn new(max_degrees: f64) -> Self {
+        assert!(max_degrees >= 0., "max_degrees must not be negative");
+        Self { max_degrees }
+    }
+}
+
+impl<D: Dimension> Transform<D> for RandomRotation {
+    fn apply(&self, x: Array<f64, D>, rng: &mut ChaCha8Rng) -> Array<f64, D> {
+        map_images(x, |image| {
+            let angle = rng.gen_range(-self.max_degrees..=self.max_degrees) * PI / 180.;
+            let (sin, cos) = angle.sin_cos();
+            let (cy, cx) = center(&image);
+            // rotate each output pixel back to where it came from
+            warp(image, |y, x| {
+                let (y, x) = (y as f64 - cy, x as f64 - cx);
+                (cy + cos * y - sin * x, cx + sin * y + cos * x)
+            });
+        })
+    }
+}
+
+/// Zoom about the center by a factor between `min` and `max`; above 1 enlarges.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RandomScale {
+    min: f64,
+    max: f64,
+}
+
+impl RandomScale {
+    pub fn new(min: f64, max: f64) -> Self {
+        assert!(0. < min && min <= max, "need 0 < min <= max");
+        Self { min, max }
+    }
+}
+
+impl<D: Dimension> Transform<D> for RandomScale {
+    fn apply(&self, x: Array<f64, D>, rng: &mut ChaCha8Rng) -> Array<f64, D> {
+        map_images(x, |image| {
+            let scale = rng.gen_range(self.min..=self.max);
+            let (cy, cx) = center(&image);
+            warp(image, |y, x| {
+                (cy + (y as f64 - cy) / scale, cx + (x as f64 - cx) / scale)
+            });
+        })
+    }
+}
+
+/// Elastic distortion (Simard et al., 2003): every pixel moves by a random displacement,
+/// smoothed with a Gaussian of width `sigma` and scaled by `alpha`. They used alpha = 34 and
+/// sigma = 4 on MNIST.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ElasticDistortion {
+    alpha: f64,
+    sigma: f64,
+}
+
+impl ElasticDistortion {
+    pub fn new(alpha: f64, sigma: f64) -> Self {
+        assert!(alpha >= 0., "alpha must not be negative");
+        assert!(sigma > 0., "sigma must be positive");
+        Self { alpha, sigma }
+    }
+
+    /// A random displacement along one axis for every pixel.
+    fn displacement(&self, h: usize, w: usize, rng: &mut ChaCha8Rng) -> Array2<f64> {
+        let radius = (3. * self.sigma).ceil() as isize;
+        let kernel = (-radius..=radius)
+            .map(|i| (-(i * i) as f64 / (2. * self.sigma * self.sigma)).exp())
+            .collect::<Vec<_>>();
+        let total = kernel.iter().sum::<f64>();
+        let noise = Uniform::new_inclusive(-1., 1.);
+        let field = Array2::from_shape_fn((h, w), |_| rng.sample(noise));
+
+        // separable blur, repeating the border
+        let blur = |field: &Array2<f64>, axis: usize| {
+            Array2::from_shape_fn((h, w), |(y, x)| {
+                let mut sum = 0.;
+                for (k, weight) in kernel.iter().enumerate() {
+                    let offset = k as isize - radius;
+                    let (y, x) = match axis {
+                        0 => ((y as isize + offset).clamp(0, h as isize - 1) as usize, x),
+                        _ => (y, (x as isize + offset).clamp(0, w as isize - 1) as usize),
+                    };
+                    sum += weight * field[[y, x]];
+                }
+                sum / total
+            })
+        };
+        blur(&blur(&field, 0), 1) * self.alpha
+    }
+}
+
+impl<D: Dimension> Transform<D> for ElasticDistortion {
+    fn apply(&self, x: Array<f64, D>, rng: &mut ChaCha8Rng) -> Array<f64, D> {
+        map_images(x, |image| {
+            let (_, h, w) = image.dim();
+            let dy = self.displacement(h, w, rng);
+            let dx = self.displacement(h, w, rng);
+            warp(image, |y, x| (y as f64 + dy[[y, x]], x as f64 + dx[[y, x]]));
+        })
+    }
+}
+
+/// Random erasing (Zhong et al., 2017): with `probability`, fill a rectangle covering a
+/// random fraction of the image with `value`.
+/// Defaults to the paper's area fraction of 0.02 to 0.4 and aspect ratio of 0.3 to 1 / 0.3.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RandomErasing {
+    probability: f64,
+    area: (f64, f64),
+    aspect_ratio: (f64, f64),
+    value: f64,
+}
+
+impl RandomErasing {
+    pub fn new(probability: f64) -> Self {
+        assert!(
+            (0. ..=1.).contains(&probability),
+            "probability must be in [0, 1]"
+        );
+        Self {
+            probability,
+            area: (0.02, 0.4),
+            aspect_ratio: (0.3, 1. / 0.3),
+            value: 0.,
+        }
+    }
+
+    /// Fraction of the image area to erase.
+    pub fn with_area(mut self, min: f64, max: f64) -> Self {
+        assert!(
+            0. < min && min <= max && max <= 1.,
+            "need 0 < min <= max <= 1"
+        );
+        self.area = (min, max);
+        self
+    }
+
+    /// Height over width of the rectangle.
+    pub fn with_aspect_ratio(mut self, min: f64, max: f64) -> Self {
+        assert!(0. < min && min <= max, "need 0 < min <= max");
+        self.aspect_ratio = (min, max);
+        self
+    }
+
+    pub fn with_value(mut self, value: f64) -> Self {
+        self.value = value;
+        self
+    }
+}
+
+impl<D: Dimension> Transform<D> for RandomErasing {
+    fn apply(&self, x: Array<f64, D>, rng: &mut ChaCha8Rng) -> Array<f64, D> {
+        map_images(x, |mut image| {
+            if !rng.gen_bool(self.probability) {
+                return;
+            }
+            let (_, h, w) = image.dim();
+            // retry until the rectangle fits, as in the paper
+            for _ in 0..10 {
+                let area = rng.gen_range(self.area.0..=self.area.1) * (h * w) as f64;
+                let ratio = rng
+                    .gen_range(self.aspect_ratio.0.ln()..=self.aspect_ratio.1.ln())
+                    .exp();
+                let eh = (area * ratio).sqrt().round() as usize;
+                let ew = (area / ratio).sqrt().round() as usize;
+                if 0 < eh && eh <= h && 0 < ew && ew <= w {
+                    let y = rng.gen_range(0..=h - eh);
+                    let x = rng.gen_range(0..=w - ew);
+                    image
+                        .slice_mut(s![.., y..y + eh, x..x + ew])
+                        .fill(self.value);
+                    break;
+                }
+            }
+        })
+    }
+}
+
+/// Add independent Gaussian noise with standard deviation `std` to every pixel.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GaussianNoise {
+    std: f64,
+}
+
+impl GaussianNoise {
+    pub fn new(std: f64) -> Self {
+        assert!(std >= 0., "std must not be negative");
+        Self { std }
+    }
+}
+
+impl<D: Dimension> Transform<D> for GaussianNoise {
+    fn apply(&self, x: Array<f64, D>, rng: &mut ChaCha8Rng) -> Array<f64, D> {
+        x.mapv_into(|x| x + self.std * rng.sample::<f64, _>(StandardNormal))
+    }
+}
+
+/// Mirror left to right with `probability`. Meant for natural images such as CIFAR; it turns
+/// digits and letters into other shapes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct HorizontalFlip {
+    probability: f64,
+}
+
+impl HorizontalFlip {
+    pub fn new(probability: f64) -> Self {
+        assert!(
+            (0. ..=1.).contains(&probability),
+            "probability must be in [0, 1]"
+        );
+        Self { probability }
+    }
+}
+
+impl<D: Dimension> Transform<D> for HorizontalFlip {
+    fn apply(&self, x: Array<f64, D>, rng: &mut ChaCha8Rng) -> Array<f64, D> {
+        map_images(x, |mut image| {
+            if rng.gen_bool(self.probability) {
+                let flipped = image.slice(s![.., .., ..;-1]).to_owned();
+                image.assign(&flipped);
+            }
+        })
+    }
+}
+
+fn print_image(x: &Array<f64, Ix3>) {
+    for row in x.index_axis(Axis(0), 0).rows() {
+        let line = row
+            .iter()
+            .map(|&v| match v {
+                v if v > 0.5 => '#',
+                v if v > 0.2 => '+',
+                _ => '.',
+            })
+            .collect::<String>();
+        println!("{line}");
+    }
+}
+
+/// Print the first MNIST test digit after each augmentation.
+pub fn run() {
+    let dataset = match MnistLoader::new()
+        .with_layout(ImageLayout::Image)
+        .with_split_sizes(Some(0), 0, Some(1))
+        .load()
+    {
+        Ok(dataset) => dataset,
+        Err(err) => return println!("{err}"),
+    };
+    let transforms: [(&str, Box<dyn Transform<Ix3>>); 7] = [
+        ("original", Box::new(|x, _: &mut ChaCha8Rng| x)),
+        ("shift", Box::new(RandomShift::new(3))),
+        ("rotation", Box::new(RandomRotation::new(20.))),
+        ("scale", Box::new(RandomScale::new(0.8, 1.2))),
+        ("elastic", Box::new(ElasticDistortion::new(34., 4.))),
+        ("erasing", Box::new(RandomErasing::new(1.))),
+        ("noise", Box::new(GaussianNoise::new(0.2))),
+    ];
+    let mut rng = ChaCha8Rng::seed_from_u64(0);
+    for (name, transform) in transforms {
+        println!("{name}");
+        print_image(&transform.apply(dataset.x_test_3d.clone(), &mut rng));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ndarray::{Array3, ArrayView3, Ix4};
+
+    use super::*;
+
+    /// A (N, 2, 4, 5) batch whose pixels all differ.
+    fn batch(n: usize) -> Array<f64, Ix4> {
+        Array::from_shape_fn((n, 2, 4, 5), |(i, c, y, x)| {
+            (i * 1000 + c * 100 + y * 10 + x) as f64
+        })
+    }
+
+    /// `image` moved down by `dy` and right by `dx`, repeating the border.
+    fn shifted(image: ArrayView3<f64>, dy: isize, dx: isize) -> Array3<f64> {
+        let (_, h, w) = image.dim();
+        Array3::from_shape_fn(image.dim(), |(c, y, x)| {
+            let sy = (y as isize - dy).clamp(0, h as isize - 1) as usize;
+            let sx = (x as isize - dx).clamp(0, w as isize - 1) as usize;
+            image[[c, sy, sx]]
+        })
+    }
+
+    #[test]
+    fn shift_moves_whole_pixels() {
+        let x = batch(32);
+        let mut rng = ChaCha8Rng::seed_from_u64(0);
+        let y = RandomShift::new(1).apply(x.clone(), &mut rng);
+        let mut seen = Vec::new();
+        for (original, image) in x.outer_iter().zip(y.outer_iter()) {
+            let offset = (-1..=1)
+                .flat_map(|dy| (-1..=1).map(move |dx| (dy, dx)))
+                .find(|&(dy, dx)| shifted(original, dy, dx) == image)
+                .expect("not a shift of at most one pixel shared by both channels");
+            seen.push(offset);
+        }
+        seen.sort();
+        seen.dedup();
+        assert_eq!(seen.len(), 9);
+
+        let y = RandomShift::new(0).apply(x.clone(), &mut rng);
+        assert_eq!(y, x);
+    }
+
+    #[test]
+    fn zero_degree_rotation_is_the_identity() {
+        let x = batch(3);
+        let mut rng = ChaCha8Rng::seed_from_u64(0);
+        assert_eq!(RandomRotation::new(0.).apply(x.clone(), &mut rng), x);
+    }
+
+    #[test]
+    fn flip_mirrors_each_row() {
+        let x = batch(3);
+        let mut rng = ChaCha8Rng::seed_from_u64(0);
+        let flip = HorizontalFlip::new(1.);
+        let y = flip.apply(x.clone(), &mut rng);
+        assert_eq!(y, x.slice(s![.., .., .., ..;-1]));
+        assert_eq!(flip.apply(y, &mut rng), x);
+        assert_eq!(HorizontalFlip::new(0.).apply(x.clone(), &mut rng), x);
+    }
+}
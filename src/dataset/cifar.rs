@@ -0,0 +1,564 @@
+//! CIFAR-10 and CIFAR-100 in their binary distribution
+//! (https://www.cs.toronto.edu/~kriz/cifar.html).
+//!
+//! ```text
+//! CIFAR-10    data_batch_1.bin .. data_batch_5.bin, test_batch.bin
+//!             record: <label> <3072 pixels>
+//! CIFAR-100   train.bin, test.bin
+//!             record: <coarse label> <fine label> <3072 pixels>
+//! ```
+//!
+//! The pixels of a record are the red, green and blue 32x32 planes, each row-major, so they
+//! map directly onto (3, 32, 32).
+
+use std::{
+    fs, io, mem,
+    ops::Range,
+    path::{Path, PathBuf},
+};
+
+use ndarray::{Array2, Array4, Ix4};
+
+use crate::{
+    ch03::mnist_dataset::{LabelFormat, Normalization, SplitSizes},
+    dataset::{
+        data_loader::{ArrayDataset, Split},
+        error::DatasetError,
+    },
+};
+
+const CHANNELS: usize = 3;
+const SIZE: usize = 32;
+const IMAGE_BYTES: usize = CHANNELS * SIZE * SIZE;
+
+const CIFAR10_CLASSES: [&str; 10] = [
+    "airplane",
+    "automobile",
+    "bird",
+    "cat",
+    "deer",
+    "dog",
+    "frog",
+    "horse",
+    "ship",
+    "truck",
+];
+
+const CIFAR100_CLASSES: [&str; 100] = [
+    "apple",
+    "aquarium_fish",
+    "baby",
+    "bear",
+    "beaver",
+    "bed",
+    "bee",
+    "beetle",
+    "bicycle",
+    "bottle",
+    "bowl",
+    "boy",
+    "bridge",
+    "bus",
+    "butterfly",
+    "camel",
+    "can",
+    "castle",
+    "caterpillar",
+    "cattle",
+    "chair",
+    "chimpanzee",
+    "clock",
+    "cloud",
+    "cockroach",
+    "couch",
+    "crab",
+    "crocodile",
+    "cup",
+    "dinosaur",
+    "dolphin",
+    "elephant",
+    "flatfish",
+    "forest",
+    "fox",
+    "girl",
+    "hamster",
+    "house",
+    "kangaroo",
+    "keyboard",
+    "lamp",
+    "lawn_mower",
+    "leopard",
+    "lion",
+    "lizard",
+    "lobster",
+    "man",
+    "maple_tree",
+    "motorcycle",
+    "mountain",
+    "mouse",
+    "mushroom",
+    "oak_tree",
+    "orange",
+    "orchid",
+    "otter",
+    "palm_tree",
+    "pear",
+    "pickup_truck",
+    "pine_tree",
+    "plain",
+    "plate",
+    "poppy",
+    "porcupine",
+    "possum",
+    "rabbit",
+    "raccoon",
+    "ray",
+    "road",
+    "rocket",
+    "rose",
+    "sea",
+    "seal",
+    "shark",
+    "shrew",
+    "skunk",
+    "skyscraper",
+    "snail",
+    "snake",
+    "spider",
+    "squirrel",
+    "streetcar",
+    "sunflower",
+    "sweet_pepper",
+    "table",
+    "tank",
+    "telephone",
+    "television",
+    "tiger",
+    "tractor",
+    "train",
+    "trout",
+    "tulip",
+    "turtle",
+    "wardrobe",
+    "whale",
+    "willow_tree",
+    "wolf",
+    "woman",
+    "worm",
+];
+
+const CIFAR100_COARSE_CLASSES: [&str; 20] = [
+    "aquatic_mammals",
+    "fish",
+    "flowers",
+    "food_containers",
+    "fruit_and_vegetables",
+    "household_electrical_devices",
+    "household_furniture",
+    "insects",
+    "large_carnivores",
+    "large_man-made_outdoor_things",
+    "large_natural_outdoor_scenes",
+    "large_omnivores_and_herbivores",
+    "medium_mammals",
+    "non-insect_invertebrates",
+    "people",
+    "reptiles",
+    "small_mammals",
+    "trees",
+    "vehicles_1",
+    "vehicles_2",
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CifarVariant {
+    Cifar10,
+    /// 100 fine classes grouped into 20 coarse superclasses.
+    Cifar100,
+}
+
+impl CifarVariant {
+    pub fn class_names(&self) -> &'static [&'static str] {
+        match self {
+            Self::Cifar10 => &CIFAR10_CLASSES,
+            Self::Cifar100 => &CIFAR100_CLASSES,
+        }
+    }
+
+    /// Empty for CIFAR-10.
+    pub fn coarse_class_names(&self) -> &'static [&'static str] {
+        match self {
+            Self::Cifar10 => &[],
+            Self::Cifar100 => &CIFAR100_COARSE_CLASSES,
+        }
+    }
+
+    fn default_data_dir(&self) -> &'static str {
+        match self {
+            Self::Cifar10 => "data/cifar-10-batches-bin/",
+            Self::Cifar100 => "data/cifar-100-binary/",
+        }
+    }
+
+    fn file_names(&self, train: bool) -> Vec<&'static str> {
+        match (self, train) {
+            (Self::Cifar10, true) => vec![
+                "data_batch_1.bin",
+                "data_batch_2.bin",
+                "data_batch_3.bin",
+                "data_batch_4.bin",
+                "data_batch_5.bin",
+            ],
+            (Self::Cifar10, false) => vec!["test_batch.bin"],
+            (Self::Cifar100, true) => vec!["train.bin"],
+            (Self::Cifar100, false) => vec!["test.bin"],
+        }
+    }
+}
+
+/// Train, validation and test splits of (N, 3, 32, 32) images. `t_*` holds the fine labels of
+/// CIFAR-100, and `t_*_coarse` its superclasses (`None` for CIFAR-10).
+pub struct CifarDataset {
+    pub class_names: &'static [&'static str],
+    pub coarse_class_names: &'static [&'static str],
+
+    pub x_train: Array4<f64>,
+    pub t_train: Array2<f64>,
+    pub t_train_coarse: Option<Array2<f64>>,
+
+    pub x_val: Array4<f64>,
+    pub t_val: Array2<f64>,
+    pub t_val_coarse: Option<Array2<f64>>,
+
+    pub x_test: Array4<f64>,
+    pub t_test: Array2<f64>,
+    pub t_test_coarse: Option<Array2<f64>>,
+}
+
+impl CifarDataset {
+    /// Move a split with its fine labels out for `DataLoader`, leaving empty arrays behind.
+    pub fn take_dataset(&mut self, split: Split) -> ArrayDataset<Ix4> {
+        let (x, t) = match split {
+            Split::Train => (&mut self.x_train, &mut self.t_train),
+            Split::Validation => (&mut self.x_val, &mut self.t_val),
+            Split::Test => (&mut self.x_test, &mut self.t_test),
+        };
+        ArrayDataset::new(mem::take(x), mem::take(t))
+    }
+}
+
+/// Loads CIFAR-10 from `data/cifar-10-batches-bin/` or CIFAR-100 from `data/cifar-100-binary/`,
+/// the directories the archives extract to. Splits, normalization and label format work as in
+/// `MnistLoader`.
+#[derive(Clone, Debug)]
+pub struct CifarLoader {
+    variant: CifarVariant,
+    data_dir: Option<PathBuf>,
+    split_sizes: SplitSizes,
+    normalization: Normalization,
+    label_format: LabelFormat,
+}
+
+/// Pixels and labels of every record of a set.
+struct Records {
+    pixels: Vec<u8>,
+    fine: Vec<u8>,
+    coarse: Vec<u8>,
+}
+
+impl CifarLoader {
+    pub fn new(variant: CifarVariant) -> Self {
+        Self {
+            variant,
+            data_dir: None,
+            split_sizes: SplitSizes::default(),
+            normalization: Normalization::default(),
+            label_format: LabelFormat::default(),
+        }
+    }
+
+    /// Replace the variant's default directory.
+    pub fn with_data_dir(mut self, data_dir: impl Into<PathBuf>) -> Self {
+        self.data_dir = Some(data_dir.into());
+        self
+    }
+
+    /// `None` for `train` or `test` takes every remaining sample.
+    pub fn with_split_sizes(
+        mut self,
+        train: Option<usize>,
+        validation: usize,
+        test: Option<usize>,
+    ) -> Self {
+        self.split_sizes = SplitSizes {
+            train,
+            validation,
+            test,
+        };
+        self
+    }
+
+    /// Checked by `load`.
+    pub fn with_normalization(mut self, normalization: Normalization) -> Self {
+        self.normalization = normalization;
+        self
+    }
+
+    /// Checked by `load`.
+    pub fn with_label_format(mut self, label_format: LabelFormat) -> Self {
+        self.label_format = label_format;
+        self
+    }
+
+    pub fn load(&self) -> Result<CifarDataset, DatasetError> {
+        self.normalization.validate()?;
+        self.label_format.validate()?;
+        let train_set = self.read_records(true)?;
+        let test_set = self.read_records(false)?;
+        let [train, validation, test] = self
+            .split_sizes
+            .resolve(train_set.fine.len(), test_set.fine.len())?;
+
+        let (mean, std) = self
+            .normalization
+            .pixel_stats(&train_set.pixels[pixel_range(train.clone())])?;
+        let classes = self.variant.class_names().len();
+        let coarse_classes = self.variant.coarse_class_names().len();
+        let split = |records: &Records, range: Range<usize>| {
+            let pixels = records.pixels[pixel_range(range.clone())]
+                .iter()
+                .map(|&x| (x as f64 - mean) / std)
+                .collect();
+            let x = Array4::from_shape_vec((range.len(), CHANNELS, SIZE, SIZE), pixels).unwrap();
+            let t = self
+                .label_format
+                .encode(&records.fine[range.clone()], classes);
+            let t_coarse = (coarse_classes > 0).then(|| {
+                self.label_format
+                    .encode(&records.coarse[range], coarse_classes)
+            });
+            (x, t, t_coarse)
+        };
+        let (x_train, t_train, t_train_coarse) = split(&train_set, train);
+        let (x_val, t_val, t_val_coarse) = split(&train_set, validation);
+        let (x_test, t_test, t_test_coarse) = split(&test_set, test);
+
+        Ok(CifarDataset {
+            class_names: self.variant.class_names(),
+            coarse_class_names: self.variant.coarse_class_names(),
+            x_train,
+            t_train,
+            t_train_coarse,
+            x_val,
+            t_val,
+            t_val_coarse,
+            x_test,
+            t_test,
+            t_test_coarse,
+        })
+    }
+
+    /// Every record of the training or test set, in file order.
+    fn read_records(&self, train: bool) -> Result<Records, DatasetError> {
+        let data_dir = match &self.data_dir {
+            Some(data_dir) => data_dir.as_path(),
+            None => Path::new(self.variant.default_data_dir()),
+        };
+        let label_bytes = match self.variant {
+            CifarVariant::Cifar10 => 1,
+            CifarVariant::Cifar100 => 2,
+        };
+        let record_bytes = label_bytes + IMAGE_BYTES;
+        let classes = self.variant.class_names().len();
+        let coarse_classes = self.variant.coarse_class_names().len();
+
+        let mut records = Records {
+            pixels: Vec::new(),
+            fine: Vec::new(),
+            coarse: Vec::new(),
+        };
+        for name in self.variant.file_names(train) {
+            let path = data_dir.join(name);
+            let bytes = fs::read(&path)
+                .map_err(|err| io::Error::new(err.kind(), format!("{}: {err}", path.display())))?;
+            if bytes.is_empty() || !bytes.len().is_multiple_of(record_bytes) {
+                return Err(DatasetError::malformed(
+                    path,
+                    format!(
+                        "{} bytes is not a whole number of {record_bytes}-byte records",
+                        bytes.len()
+                    ),
+                ));
+            }
+
+            for record in bytes.chunks_exact(record_bytes) {
+                let (labels, pixels) = record.split_at(label_bytes);
+                let (fine, coarse) = match *labels {
+                    [fine] => (fine, None),
+                    [coarse, fine] => (fine, Some(coarse)),
+                    _ => unreachable!(),
+                };
+                if fine as usize >= classes {
+                    return Err(DatasetError::malformed(
+                        path,
+                        format!("label {fine} is not below {classes}"),
+                    ));
+                }
+                if let Some(coarse) = coarse {
+                    if coarse as usize >= coarse_classes {
+                        return Err(DatasetError::malformed(
+                            path,
+                            format!("coarse label {coarse} is not below {coarse_classes}"),
+                        ));
+                    }
+                    records.coarse.push(coarse);
+                }
+                records.fine.push(fine);
+                records.pixels.extend_from_slice(pixels);
+            }
+        }
+        Ok(records)
+    }
+}
+
+/// Indices into the pixel buffer of the records in `range`.
+fn pixel_range(range: Range<usize>) -> Range<usize> {
+    range.start * IMAGE_BYTES..range.end * IMAGE_BYTES
+}
+
+/// Load CIFAR-10 and print the split shapes and the class of the first training images.
+pub fn run() {
+    match CifarLoader::new(CifarVariant::Cifar10)
+        .with_split_sizes(Some(1000), 0, Some(1000))
+        .load()
+    {
+        Ok(dataset) => {
+            println!(
+                "x_train {:?} | t_train {:?} | x_test {:?}",
+                dataset.x_train.shape(),
+                dataset.t_train.shape(),
+                dataset.x_test.shape()
+            );
+            for t in dataset.t_train.rows().into_iter().take(5) {
+                let label = t.iter().position(|&t| t == 1.).unwrap();
+                println!("{}", dataset.class_names[label]);
+            }
+        }
+        Err(err) => println!("{err}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A record with `labels` and pixel `k` set to `(k + shift) % 256`.
+    fn record(labels: &[u8], shift: usize) -> Vec<u8> {
+        let mut record = labels.to_vec();
+        record.extend((0..IMAGE_BYTES).map(|k| ((k + shift) % 256) as u8));
+        record
+    }
+
+    /// Write `files` of records into a fresh directory named `name` under the temp dir.
+    fn data_dir(name: &str, files: &[(&str, Vec<Vec<u8>>)]) -> PathBuf {
+        let dir = std::env::temp_dir().join(name);
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        for (file, records) in files {
+            fs::write(dir.join(file), records.concat()).unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn cifar10_records_and_splits() {
+        // one record per training file, labelled by its number
+        let mut files = CifarVariant::Cifar10
+            .file_names(true)
+            .into_iter()
+            .zip(1..)
+            .map(|(name, i)| (name, vec![record(&[i as u8], i)]))
+            .collect::<Vec<_>>();
+        files.push(("test_batch.bin", vec![record(&[9], 0), record(&[0], 100)]));
+        let dir = data_dir("cifar10_test", &files);
+
+        let dataset = CifarLoader::new(CifarVariant::Cifar10)
+            .with_data_dir(&dir)
+            .with_split_sizes(Some(3), 2, None)
+            .with_normalization(Normalization::None)
+            .with_label_format(LabelFormat::Index)
+            .load()
+            .unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(dataset.x_train.shape(), [3, 3, 32, 32]);
+        assert_eq!(dataset.t_train.column(0).to_vec(), [1., 2., 3.]);
+        assert_eq!(dataset.t_val.column(0).to_vec(), [4., 5.]);
+        assert_eq!(dataset.t_test.column(0).to_vec(), [9., 0.]);
+        assert!(dataset.t_train_coarse.is_none());
+        // second record of the training files, blue plane, row 5, column 7
+        let k = 2 * 32 * 32 + 5 * 32 + 7;
+        assert_eq!(dataset.x_train[[1, 2, 5, 7]], ((k + 2) % 256) as f64);
+        assert_eq!(dataset.x_test[[1, 0, 0, 0]], 100.);
+    }
+
+    #[test]
+    fn cifar100_reads_the_coarse_label_first() {
+        let dir = data_dir(
+            "cifar100_test",
+            &[
+                ("train.bin", vec![record(&[19, 42], 0), record(&[3, 7], 1)]),
+                ("test.bin", vec![record(&[0, 99], 2)]),
+            ],
+        );
+        let dataset = CifarLoader::new(CifarVariant::Cifar100)
+            .with_data_dir(&dir)
+            .with_label_format(LabelFormat::Index)
+            .load()
+            .unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(dataset.t_train.column(0).to_vec(), [42., 7.]);
+        assert_eq!(
+            dataset.t_train_coarse.unwrap().column(0).to_vec(),
+            [19., 3.]
+        );
+        assert_eq!(dataset.t_test.column(0).to_vec(), [99.]);
+        assert_eq!(dataset.x_train[[1, 0, 0, 1]], 2. / 255.);
+    }
+
+    #[test]
+    fn malformed_files_and_settings_are_errors() {
+        let mut truncated = record(&[0, 1], 0);
+        truncated.pop();
+        let dir = data_dir(
+            "cifar100_malformed_test",
+            &[
+                ("train.bin", vec![truncated]),
+                ("test.bin", vec![record(&[20, 0], 0)]),
+            ],
+        );
+        let loader = CifarLoader::new(CifarVariant::Cifar100).with_data_dir(&dir);
+        let err = loader.load().err().unwrap();
+        assert!(
+            err.to_string()
+                .contains("whole number of 3074-byte records"),
+            "{err}"
+        );
+
+        fs::write(dir.join("train.bin"), record(&[0, 1], 0)).unwrap();
+        let err = loader.load().err().unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(
+            err.to_string().contains("coarse label 20 is not below 20"),
+            "{err}"
+        );
+
+        let err = loader
+            .with_label_format(LabelFormat::Smoothed(-0.1))
+            .load()
+            .err()
+            .unwrap();
+        assert!(matches!(err, DatasetError::InvalidConfig(_)), "{err}");
+    }
+}
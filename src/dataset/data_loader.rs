@@ -0,0 +1,570 @@
+//! Datasets indexable by sample and a loader that turns them into epochs of mini-batches.
+
+use std::{
+    iter::{Enumerate, Skip},
+    marker::PhantomData,
+    panic,
+    sync::{
+        Arc, Condvar, Mutex,
+        mpsc::{Receiver, sync_channel},
+    },
+    thread::{self, JoinHandle},
+    vec,
+};
+
+use ndarray::{Array, Array1, Array2, Axis, Dimension, Ix2, RemoveAxis, stack};
+use ndarray_rand::rand::{
+    Rng, SeedableRng,
+    distributions::{Distribution, WeightedIndex},
+    seq::{SliceRandom, index::sample},
+};
+use rand_chacha::ChaCha8Rng;
+
+use crate::dataset::transform::Transform;
+
+/// A collection of (input, label) samples, indexed from 0. `D` is the dimension of a batch of
+/// inputs, so a single input has one axis less.
+pub trait Dataset<D: RemoveAxis = Ix2> {
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Input and label of sample `index`.
+    fn get(&self, index: usize) -> (Array<f64, D::Smaller>, Array1<f64>);
+
+    /// Inputs and labels of `indices`, stacked in order. `indices` must not be empty.
+    fn get_batch(&self, indices: &[usize]) -> Batch<D> {
+        let samples = indices.iter().map(|&i| self.get(i)).collect::<Vec<_>>();
+        let x = samples.iter().map(|(x, _)| x.view()).collect::<Vec<_>>();
+        let t = samples.iter().map(|(_, t)| t.view()).collect::<Vec<_>>();
+        (
+            stack(Axis(0), &x).unwrap().into_dimensionality().unwrap(),
+            stack(Axis(0), &t).unwrap(),
+        )
+    }
+}
+
+/// Inputs and labels of a mini-batch.
+pub type Batch<D = Ix2> = (Array<f64, D>, Array2<f64>);
+
+/// One of the three splits of a loaded dataset.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Split {
+    Train,
+    Validation,
+    Test,
+}
+
+/// Inputs and labels held in memory, one sample per row. Cloning shares the arrays.
+#[derive(Clone, Debug)]
+pub struct ArrayDataset<D: RemoveAxis = Ix2> {
+    x: Arc<Array<f64, D>>,
+    t: Arc<Array2<f64>>,
+}
+
+impl<D: RemoveAxis> ArrayDataset<D> {
+    pub fn new(x: Array<f64, D>, t: Array2<f64>) -> Self {
+        assert_eq!(
+            x.len_of(Axis(0)),
+            t.nrows(),
+            "inputs and labels differ in length"
+        );
+        Self {
+            x: Arc::new(x),
+            t: Arc::new(t),
+        }
+    }
+
+    pub fn x(&self) -> &Array<f64, D> {
+        &self.x
+    }
+
+    pub fn t(&self) -> &Array2<f64> {
+        &self.t
+    }
+}
+
+impl<D: RemoveAxis> Dataset<D> for ArrayDataset<D> {
+    fn len(&self) -> usize {
+        self.t.nrows()
+    }
+
+    fn get(&self, index: usize) -> (Array<f64, D::Smaller>, Array1<f64>) {
+        (
+            self.x.index_axis(Axis(0), index).to_owned(),
+            self.t.row(index).to_owned(),
+        )
+    }
+
+    fn get_batch(&self, indices: &[usize]) -> Batch<D> {
+        (
+            self.x.select(Axis(0), indices),
+            self.t.select(Axis(0), indices),
+        )
+    }
+}
+
+/// The order in which an epoch visits the samples.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Sampler {
+    /// Every sample once, in index order.
+    Sequential,
+    /// Every sample once, in a new random order each epoch.
+    Shuffle,
+    /// `len / batch_size` batches (at least one for a non-empty dataset), each drawn independently without
+    /// replacement, as in the book's training loops. Samples may repeat across batches and
+    /// `drop_last` does not apply.
+    RandomBatches,
+    /// `len` samples drawn with replacement, sample `i` with probability proportional to
+    /// `weights[i]`, e.g. to balance classes. `DataLoader::with_sampler` checks the weights.
+    Weighted(Vec<f64>),
+}
+
+impl Sampler {
+    /// Number of batches in an epoch over `len` samples.
+    pub fn num_batches(&self, len: usize, batch_size: usize, drop_last: bool) -> usize {
+        match self {
+            Self::RandomBatches if len == 0 => 0,
+            Self::RandomBatches => 1.max(len / batch_size),
+            _ if drop_last => len / batch_size,
+            _ => len.div_ceil(batch_size),
+        }
+    }
+
+    /// Indices of every batch of one epoch.
+    pub fn epoch(
+        &self,
+        len: usize,
+        batch_size: usize,
+        drop_last: bool,
+        rng: &mut impl Rng,
+    ) -> Vec<Vec<usize>> {
+        assert!(batch_size > 0, "batch_size must be positive");
+        if len == 0 {
+            return Vec::new();
+        }
+        let order = match self {
+            Self::Sequential => (0..len).collect::<Vec<_>>(),
+            Self::Shuffle => {
+                let mut order = (0..len).collect::<Vec<_>>();
+                order.shuffle(rng);
+                order
+            }
+            Self::RandomBatches => {
+                let batch_size = batch_size.min(len);
+                return (0..self.num_batches(len, batch_size, drop_last))
+                    .map(|_| sample(rng, len, batch_size).into_vec())
+                    .collect();
+            }
+            Self::Weighted(weights) => {
+                assert_eq!(weights.len(), len, "need one weight per sample");
+                let dist = WeightedIndex::new(weights).expect("invalid sampling weights");
+                (0..len).map(|_| dist.sample(rng)).collect()
+            }
+        };
+        order
+            .chunks(batch_size)
+            .take(self.num_batches(len, batch_size, drop_last))
+            .map(|batch| batch.to_vec())
+            .collect()
+    }
+}
+
+/// Yields a dataset as mini-batches of (x_batch, t_batch), one epoch at a time.
+/// By default batches are shuffled, the last one may be smaller, and the order comes from an
+/// entropy-seeded ChaCha8 stream.
+pub struct DataLoader<S, D = Ix2> {
+    dataset: S,
+    batch_size: usize,
+    drop_last: bool,
+    sampler: Sampler,
+    transforms: Vec<Arc<dyn Transform<D>>>,
+    /// (batches ahead, worker threads)
+    prefetch: Option<(usize, usize)>,
+    rng: ChaCha8Rng,
+}
+
+impl<S, D> DataLoader<S, D>
+where
+    S: Dataset<D> + Clone + Send + 'static,
+    D: RemoveAxis + Send + 'static,
+{
+    pub fn new(dataset: S, batch_size: usize) -> Self {
+        assert!(batch_size > 0, "batch_size must be positive");
+        Self {
+            dataset,
+            batch_size,
+            drop_last: false,
+            sampler: Sampler::Shuffle,
+            transforms: Vec::new(),
+            prefetch: None,
+            rng: ChaCha8Rng::from_entropy(),
+        }
+    }
+
+    /// Panics unless a `Sampler::Weighted` has one non-negative weight per sample, some of them
+    /// positive.
+    pub fn with_sampler(mut self, sampler: Sampler) -> Self {
+        if let Sampler::Weighted(weights) = &sampler {
+            assert_eq!(
+                weights.len(),
+                self.dataset.len(),
+                "need one weight per sample"
+            );
+            assert!(
+                weights.is_empty() || WeightedIndex::new(weights).is_ok(),
+                "sampling weights must be non-negative and not all zero"
+            );
+        }
+        self.sampler = sampler;
+        self
+    }
+
+    /// Skip the last batch of an epoch when it would be smaller than `batch_size`.
+    pub fn with_drop_last(mut self, drop_last: bool) -> Self {
+        self.drop_last = drop_last;
+        self
+    }
+
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.rng = ChaCha8Rng::seed_from_u64(seed);
+        self
+    }
+
+    /// Apply `transform` to every batch of inputs, after the transforms added before it.
+    pub fn with_transform(mut self, transform: impl Transform<D> + 'static) -> Self {
+        self.transforms.push(Arc::new(transform));
+        self
+    }
+
+    /// Assemble up to `batches` batches ahead on `workers` background threads: at most `batches`
+    /// batches are ready or in progress beyond the last one taken. The batches and their order
+    /// are the same as without prefetching.
+    pub fn with_prefetch(mut self, batches: usize, workers: usize) -> Self {
+        assert!(batches > 0, "batches must be positive");
+        assert!(workers > 0, "workers must be positive");
+        self.prefetch = Some((batches, workers));
+        self
+    }
+
+    pub fn dataset(&self) -> &S {
+        &self.dataset
+    }
+
+    pub fn batch_size(&self) -> usize {
+        self.batch_size
+    }
+
+    pub fn num_batches(&self) -> usize {
+        self.sampler
+            .num_batches(self.dataset.len(), self.batch_size, self.drop_last)
+    }
+
+    /// The stream the next epoch's order will be drawn from.
+    pub fn rng(&self) -> &ChaCha8Rng {
+        &self.rng
+    }
+
+    pub fn set_rng(&mut self, rng: ChaCha8Rng) {
+        self.rng = rng;
+    }
+
+    /// Draw the order of the next epoch.
+    pub fn epoch(&mut self) -> Batches<S, D> {
+        self.epoch_from(0)
+    }
+
+    /// Draw the order of the next epoch and start at batch `first`, like `epoch().skip(first)`
+    /// but without assembling the skipped batches.
+    pub fn epoch_from(&mut self, first: usize) -> Batches<S, D> {
+        let batches = self.sampler.epoch(
+            self.dataset.len(),
+            self.batch_size,
+            self.drop_last,
+            &mut self.rng,
+        );
+        let assembler = Assembler {
+            dataset: self.dataset.clone(),
+            transforms: self.transforms.clone(),
+            seed: self.rng.r#gen(),
+            _dim: PhantomData,
+        };
+        let batches = batches.into_iter().enumerate().skip(first);
+        let source = match self.prefetch {
+            None => Source::Inline(assembler, batches),
+            Some((ahead, workers)) => Source::Prefetch(Prefetch::spawn(
+                assembler,
+                batches.collect(),
+                ahead,
+                workers,
+            )),
+        };
+        Batches { source }
+    }
+}
+
+/// Builds batches from their indices, on the training thread or on a worker.
+struct Assembler<S, D> {
+    dataset: S,
+    transforms: Vec<Arc<dyn Transform<D>>>,
+    /// Seeds the transforms of every batch of the epoch, each on its own stream.
+    seed: u64,
+    _dim: PhantomData<D>,
+}
+
+impl<S, D> Assembler<S, D>
+where
+    S: Dataset<D>,
+    D: RemoveAxis,
+{
+    fn assemble(&self, batch: usize, indices: &[usize]) -> Batch<D> {
+        let (mut x, t) = self.dataset.get_batch(indices);
+        if !self.transforms.is_empty() {
+            let mut rng = ChaCha8Rng::seed_from_u64(self.seed);
+            rng.set_stream(batch as u64);
+            for transform in &self.transforms {
+                x = transform.apply(x, &mut rng);
+            }
+        }
+        (x, t)
+    }
+}
+
+/// Batches assembled ahead by worker threads. Worker `w` builds every `workers`-th batch
+/// starting at the `w`-th and hands them over through its own bounded channel, so reading the
+/// channels in turn restores the order.
+struct Prefetch<D: Dimension> {
+    receivers: Vec<Receiver<Batch<D>>>,
+    handles: Vec<JoinHandle<()>>,
+    window: Arc<(Mutex<Window>, Condvar)>,
+    next: usize,
+    remaining: usize,
+}
+
+/// Shared by the workers so that none starts a batch more than `ahead` batches past the last
+/// one taken, whichever worker it belongs to.
+struct Window {
+    /// Batches taken from the workers so far.
+    taken: usize,
+    /// Set when the epoch is dropped, to release the waiting workers.
+    closed: bool,
+}
+
+impl<D> Prefetch<D>
+where
+    D: RemoveAxis + Send + 'static,
+{
+    fn spawn<S>(
+        assembler: Assembler<S, D>,
+        batches: Vec<(usize, Vec<usize>)>,
+        ahead: usize,
+        workers: usize,
+    ) -> Self
+    where
+        S: Dataset<D> + Clone + Send + 'static,
+    {
+        let workers = workers.min(batches.len()).max(1);
+        let remaining = batches.len();
+        let window = Arc::new((
+            Mutex::new(Window {
+                taken: 0,
+                closed: false,
+            }),
+            Condvar::new(),
+        ));
+        let (receivers, handles) = (0..workers)
+            .map(|worker| {
+                let share = batches
+                    .iter()
+                    .cloned()
+                    .enumerate()
+                    .skip(worker)
+                    .step_by(workers)
+                    .collect::<Vec<_>>();
+                let window = window.clone();
+                let assembler = Assembler {
+                    dataset: assembler.dataset.clone(),
+                    transforms: assembler.transforms.clone(),
+                    seed: assembler.seed,
+                    _dim: PhantomData,
+                };
+                let (sender, receiver) = sync_channel(ahead);
+                let handle = thread::spawn(move || {
+                    for (position, (batch, indices)) in share {
+                        let (lock, ready) = &*window;
+                        let closed = ready
+                            .wait_while(lock.lock().unwrap(), |window| {
+                                !window.closed && position >= window.taken + ahead
+                            })
+                            .unwrap()
+                            .closed;
+                        // the receiver is gone when the epoch was dropped early
+                        if closed || sender.send(assembler.assemble(batch, &indices)).is_err() {
+                            break;
+                        }
+                    }
+                });
+                (receiver, handle)
+            })
+            .unzip();
+        Self {
+            receivers,
+            handles,
+            window,
+            next: 0,
+            remaining,
+        }
+    }
+
+    fn next(&mut self) -> Option<Batch<D>> {
+        if self.remaining == 0 {
+            return None;
+        }
+        let worker = self.next % self.receivers.len();
+        match self.receivers[worker].recv() {
+            Ok(batch) => {
+                self.next += 1;
+                self.remaining -= 1;
+                let (lock, ready) = &*self.window;
+                lock.lock().unwrap().taken += 1;
+                ready.notify_all();
+                Some(batch)
+            }
+            // a worker only hangs up early when it panicked
+            Err(_) => match self.handles.swap_remove(worker).join() {
+                Err(payload) => panic::resume_unwind(payload),
+                Ok(()) => unreachable!("worker stopped before its last batch"),
+            },
+        }
+    }
+}
+
+impl<D: Dimension> Drop for Prefetch<D> {
+    fn drop(&mut self) {
+        // release workers waiting for the window and disconnect those blocked on a full channel
+        let (lock, ready) = &*self.window;
+        lock.lock().unwrap().closed = true;
+        ready.notify_all();
+        self.receivers.clear();
+        for handle in self.handles.drain(..) {
+            let _ = handle.join();
+        }
+    }
+}
+
+enum Source<S, D: Dimension> {
+    Inline(Assembler<S, D>, Skip<Enumerate<vec::IntoIter<Vec<usize>>>>),
+    Prefetch(Prefetch<D>),
+}
+
+/// The mini-batches of one epoch, see `DataLoader::epoch`.
+pub struct Batches<S, D: Dimension = Ix2> {
+    source: Source<S, D>,
+}
+
+impl<S, D> Iterator for Batches<S, D>
+where
+    S: Dataset<D>,
+    D: RemoveAxis + Send + 'static,
+{
+    type Item = Batch<D>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match &mut self.source {
+            Source::Inline(assembler, batches) => {
+                let (batch, indices) = batches.next()?;
+                Some(assembler.assemble(batch, &indices))
+            }
+            Source::Prefetch(prefetch) => prefetch.next(),
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = match &self.source {
+            Source::Inline(_, batches) => batches.len(),
+            Source::Prefetch(prefetch) => prefetch.remaining,
+        };
+        (remaining, Some(remaining))
+    }
+}
+
+impl<S, D> ExactSizeIterator for Batches<S, D>
+where
+    S: Dataset<D>,
+    D: RemoveAxis + Send + 'static,
+{
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `len` samples with one feature each, equal to their index.
+    fn dataset(len: usize) -> ArrayDataset {
+        let x = Array2::from_shape_fn((len, 1), |(i, _)| i as f64);
+        ArrayDataset::new(x, Array2::zeros((len, 1)))
+    }
+
+    #[test]
+    fn empty_dataset_has_empty_epochs() {
+        for sampler in [
+            Sampler::Sequential,
+            Sampler::Shuffle,
+            Sampler::RandomBatches,
+            Sampler::Weighted(Vec::new()),
+        ] {
+            let mut loader = DataLoader::new(dataset(0), 4).with_sampler(sampler.clone());
+            assert_eq!(loader.num_batches(), 0, "{sampler:?}");
+            assert_eq!(loader.epoch().count(), 0, "{sampler:?}");
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "not all zero")]
+    fn all_zero_weights_are_rejected_up_front() {
+        DataLoader::new(dataset(3), 2).with_sampler(Sampler::Weighted(vec![0.; 3]));
+    }
+
+    #[test]
+    #[should_panic(expected = "one weight per sample")]
+    fn weights_must_match_the_dataset() {
+        DataLoader::new(dataset(3), 2).with_sampler(Sampler::Weighted(vec![1.; 2]));
+    }
+
+    #[test]
+    fn prefetch_stays_within_its_bound() {
+        use std::{
+            sync::atomic::{AtomicUsize, Ordering},
+            time::Duration,
+        };
+
+        let started = Arc::new(AtomicUsize::new(0));
+        let count = started.clone();
+        let mut loader = DataLoader::new(dataset(40), 2)
+            .with_sampler(Sampler::Sequential)
+            .with_transform(move |x: Array2<f64>, _: &mut ChaCha8Rng| {
+                count.fetch_add(1, Ordering::SeqCst);
+                x
+            })
+            .with_prefetch(3, 4);
+        for (taken, (x, _)) in loader.epoch().enumerate() {
+            assert_eq!(x[[0, 0]], (2 * taken) as f64, "batches stay in order");
+            // give the workers time to run as far ahead as they may
+            thread::sleep(Duration::from_millis(5));
+            let ahead = started.load(Ordering::SeqCst) - (taken + 1);
+            assert!(
+                ahead <= 3,
+                "{ahead} batches ahead after taking {}",
+                taken + 1
+            );
+        }
+        assert_eq!(started.load(Ordering::SeqCst), 20);
+
+        // dropping an epoch early releases the waiting workers
+        let mut epoch = loader.epoch();
+        epoch.next();
+        drop(epoch);
+    }
+}
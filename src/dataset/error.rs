@@ -0,0 +1,61 @@
+use std::{error::Error, fmt, io, path::PathBuf};
+
+/// Why a dataset could not be loaded.
+#[derive(Debug)]
+pub enum DatasetError {
+    /// A file is missing or unreadable, or is not in the expected format. The message names it.
+    Io(io::Error),
+    /// A file was read but its contents do not fit the dataset, e.g. images of the wrong size.
+    Malformed { path: PathBuf, reason: String },
+    /// The loader's settings cannot be used, e.g. a non-positive `std`, or `Standardize` on a
+    /// training split whose pixels do not vary.
+    InvalidConfig(String),
+    /// More samples were requested for `split` than the files hold.
+    SplitTooLarge {
+        split: &'static str,
+        requested: usize,
+        available: usize,
+    },
+}
+
+impl DatasetError {
+    pub(crate) fn malformed(path: impl Into<PathBuf>, reason: impl Into<String>) -> Self {
+        Self::Malformed {
+            path: path.into(),
+            reason: reason.into(),
+        }
+    }
+}
+
+impl fmt::Display for DatasetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "{err}"),
+            Self::Malformed { path, reason } => write!(f, "{}: {reason}", path.display()),
+            Self::InvalidConfig(reason) => write!(f, "{reason}"),
+            Self::SplitTooLarge {
+                split,
+                requested,
+                available,
+            } => write!(
+                f,
+                "requested {requested} {split} samples, but only {available} are available"
+            ),
+        }
+    }
+}
+
+impl Error for DatasetError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for DatasetError {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
@@ -0,0 +1,292 @@
+//! IDX files (http://yann.lecun.com/exdb/mnist/), the format of MNIST and its relatives.
+//!
+//! ```text
+//! magic   4 bytes   0x00 0x00 <dtype> <ndim>
+//! dims    ndim x u32 big-endian
+//! data    product(dims) elements of dtype, big-endian, row-major
+//!
+//! dtype   0x08 u8, 0x09 i8, 0x0B i16, 0x0C i32, 0x0D f32, 0x0E f64
+//! ```
+//!
+//! Files ending in `.gz` are decompressed on the fly.
+
+use std::{
+    fs::File,
+    io::{self, BufReader, Read},
+    path::{Path, PathBuf},
+};
+
+use flate2::read::GzDecoder;
+use ndarray::{ArrayD, IxDyn};
+
+fn invalid_data(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+/// The contents of an IDX file, in the element type it was stored with.
+#[derive(Clone, Debug, PartialEq)]
+pub enum IdxArray {
+    U8(ArrayD<u8>),
+    I8(ArrayD<i8>),
+    I16(ArrayD<i16>),
+    I32(ArrayD<i32>),
+    F32(ArrayD<f32>),
+    F64(ArrayD<f64>),
+}
+
+impl IdxArray {
+    pub fn shape(&self) -> &[usize] {
+        match self {
+            Self::U8(x) => x.shape(),
+            Self::I8(x) => x.shape(),
+            Self::I16(x) => x.shape(),
+            Self::I32(x) => x.shape(),
+            Self::F32(x) => x.shape(),
+            Self::F64(x) => x.shape(),
+        }
+    }
+
+    /// Every element converted to f64, which is exact for all IDX types.
+    pub fn to_f64(&self) -> ArrayD<f64> {
+        match self {
+            Self::U8(x) => x.mapv(f64::from),
+            Self::I8(x) => x.mapv(f64::from),
+            Self::I16(x) => x.mapv(f64::from),
+            Self::I32(x) => x.mapv(f64::from),
+            Self::F32(x) => x.mapv(f64::from),
+            Self::F64(x) => x.clone(),
+        }
+    }
+
+    /// The array if it holds unsigned bytes, as MNIST images and labels do.
+    pub fn into_u8(self) -> Option<ArrayD<u8>> {
+        match self {
+            Self::U8(x) => Some(x),
+            _ => None,
+        }
+    }
+}
+
+fn decode<T, const N: usize>(bytes: &[u8], from_be_bytes: fn([u8; N]) -> T) -> Vec<T> {
+    bytes
+        .chunks_exact(N)
+        .map(|b| from_be_bytes(b.try_into().unwrap()))
+        .collect()
+}
+
+/// Read one IDX array. Fails on an unknown magic number, on truncated data and on bytes past
+/// the end of the data.
+pub fn read_idx(r: &mut impl Read) -> io::Result<IdxArray> {
+    let mut magic = [0; 4];
+    r.read_exact(&mut magic)
+        .map_err(|_| invalid_data("not an IDX file (too short for the magic number)"))?;
+    let [0, 0, dtype, ndim] = magic else {
+        return Err(invalid_data(format!(
+            "not an IDX file (bad magic number {:#010x})",
+            u32::from_be_bytes(magic)
+        )));
+    };
+    let size = match dtype {
+        0x08 | 0x09 => 1,
+        0x0B => 2,
+        0x0C | 0x0D => 4,
+        0x0E => 8,
+        _ => return Err(invalid_data(format!("unknown IDX dtype {dtype:#04x}"))),
+    };
+
+    let mut dims = vec![0; ndim as usize * 4];
+    r.read_exact(&mut dims)
+        .map_err(|_| invalid_data("truncated IDX header"))?;
+    let shape = decode(&dims, u32::from_be_bytes)
+        .into_iter()
+        .map(|dim| dim as usize)
+        .collect::<Vec<_>>();
+    let len = shape
+        .iter()
+        .try_fold(size, |len: usize, &dim| len.checked_mul(dim))
+        .ok_or_else(|| invalid_data(format!("IDX shape {shape:?} is too large")))?;
+
+    // read incrementally rather than trusting the header with one large allocation
+    let mut bytes = Vec::new();
+    r.take(len as u64).read_to_end(&mut bytes)?;
+    if bytes.len() != len {
+        return Err(invalid_data(format!(
+            "truncated IDX data: expected {len} bytes for shape {shape:?}, found {}",
+            bytes.len()
+        )));
+    }
+    if r.read(&mut [0])? != 0 {
+        return Err(invalid_data("unexpected bytes after the IDX data"));
+    }
+
+    let shape = IxDyn(&shape);
+    let array = match dtype {
+        0x08 => IdxArray::U8(ArrayD::from_shape_vec(shape, bytes).unwrap()),
+        0x09 => {
+            IdxArray::I8(ArrayD::from_shape_vec(shape, decode(&bytes, i8::from_be_bytes)).unwrap())
+        }
+        0x0B => IdxArray::I16(
+            ArrayD::from_shape_vec(shape, decode(&bytes, i16::from_be_bytes)).unwrap(),
+        ),
+        0x0C => IdxArray::I32(
+            ArrayD::from_shape_vec(shape, decode(&bytes, i32::from_be_bytes)).unwrap(),
+        ),
+        0x0D => IdxArray::F32(
+            ArrayD::from_shape_vec(shape, decode(&bytes, f32::from_be_bytes)).unwrap(),
+        ),
+        _ => IdxArray::F64(
+            ArrayD::from_shape_vec(shape, decode(&bytes, f64::from_be_bytes)).unwrap(),
+        ),
+    };
+    Ok(array)
+}
+
+/// Read the IDX file at `path`, gunzipping it if the name ends in `.gz`.
+/// Errors name the file.
+pub fn load_idx(path: impl AsRef<Path>) -> io::Result<IdxArray> {
+    let path = path.as_ref();
+    let with_path =
+        |err: io::Error| io::Error::new(err.kind(), format!("{}: {err}", path.display()));
+
+    let mut file = BufReader::new(File::open(path).map_err(with_path)?);
+    if path.extension().is_some_and(|ext| ext == "gz") {
+        read_idx(&mut GzDecoder::new(file))
+    } else {
+        read_idx(&mut file)
+    }
+    .map_err(with_path)
+}
+
+/// The first of `names` that exists in `dir`, trying each with and without a `.gz` suffix.
+/// The error lists every file that was looked for.
+pub fn find_idx(dir: impl AsRef<Path>, names: &[&str]) -> io::Result<PathBuf> {
+    let dir = dir.as_ref();
+    let candidates = names
+        .iter()
+        .flat_map(|name| [dir.join(name), dir.join(format!("{name}.gz"))])
+        .collect::<Vec<_>>();
+    candidates
+        .iter()
+        .find(|path| path.is_file())
+        .cloned()
+        .ok_or_else(|| {
+            let tried = candidates
+                .iter()
+                .map(|path| path.display().to_string())
+                .collect::<Vec<_>>()
+                .join(", ");
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no IDX file found; tried {tried}"),
+            )
+        })
+}
+
+/// Print the shape and first entries of the MNIST label files in `data/`.
+pub fn run() {
+    for names in [
+        ["train-labels-idx1-ubyte", "train-labels.idx1-ubyte"],
+        ["t10k-labels-idx1-ubyte", "t10k-labels.idx1-ubyte"],
+    ] {
+        match find_idx("data/", &names).and_then(load_idx) {
+            Ok(labels) => {
+                let first = labels.to_f64().iter().take(10).copied().collect::<Vec<_>>();
+                println!("{}: shape {:?}, first {first:?}", names[0], labels.shape());
+            }
+            Err(err) => println!("{err}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, io::Write};
+
+    use flate2::{Compression, write::GzEncoder};
+    use ndarray::arr3;
+
+    use super::*;
+
+    fn header(dtype: u8, shape: &[u32]) -> Vec<u8> {
+        let mut bytes = vec![0, 0, dtype, shape.len() as u8];
+        bytes.extend(shape.iter().flat_map(|dim| dim.to_be_bytes()));
+        bytes
+    }
+
+    fn read(bytes: &[u8]) -> io::Result<IdxArray> {
+        read_idx(&mut &bytes[..])
+    }
+
+    #[test]
+    fn reads_u8_images_in_row_major_order() {
+        let mut bytes = header(0x08, &[2, 2, 3]);
+        bytes.extend(0..12);
+        let array = read(&bytes).unwrap();
+        assert_eq!(array.shape(), [2, 2, 3]);
+        let expected = arr3(&[[[0, 1, 2], [3, 4, 5]], [[6, 7, 8], [9, 10, 11]]]).into_dyn();
+        assert_eq!(array.into_u8(), Some(expected));
+    }
+
+    #[test]
+    fn decodes_big_endian_elements() {
+        let mut bytes = header(0x0B, &[3]);
+        bytes.extend([0x01, 0x02, 0xFF, 0xFE, 0x80, 0x00]);
+        let array = read(&bytes).unwrap();
+        assert_eq!(
+            array.to_f64().into_raw_vec_and_offset().0,
+            [258., -2., -32768.]
+        );
+        assert_eq!(array.into_u8(), None);
+
+        let mut bytes = header(0x0D, &[2]);
+        bytes.extend(1.5f32.to_be_bytes());
+        bytes.extend((-0.25f32).to_be_bytes());
+        let expected = ArrayD::from_shape_vec(IxDyn(&[2]), vec![1.5f32, -0.25]).unwrap();
+        assert_eq!(read(&bytes).unwrap(), IdxArray::F32(expected));
+    }
+
+    #[test]
+    fn malformed_files_are_errors() {
+        let message = |bytes: &[u8]| {
+            let err = read(bytes).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+            err.to_string()
+        };
+        let mut data = header(0x08, &[2, 2]);
+        data.extend([1, 2, 3]);
+        assert!(message(&data).starts_with("truncated IDX data: expected 4 bytes"));
+        data.extend([4, 5]);
+        assert_eq!(message(&data), "unexpected bytes after the IDX data");
+
+        assert!(message(&[1, 0, 0x08, 1]).starts_with("not an IDX file (bad magic number"));
+        assert!(message(&[0, 0]).starts_with("not an IDX file (too short"));
+        assert!(message(&header(0x0A, &[1])).starts_with("unknown IDX dtype"));
+        assert_eq!(message(&header(0x08, &[1])[..6]), "truncated IDX header");
+        let huge = header(0x0E, &[u32::MAX; 3]);
+        assert!(message(&huge).ends_with("is too large"));
+    }
+
+    #[test]
+    fn load_idx_gunzips_and_names_the_file() {
+        let dir = std::env::temp_dir().join("idx_load_test");
+        fs::create_dir_all(&dir).unwrap();
+        let mut bytes = header(0x08, &[3]);
+        bytes.extend([7, 8, 9]);
+        let gz = dir.join("labels-idx1-ubyte.gz");
+        let mut encoder = GzEncoder::new(File::create(&gz).unwrap(), Compression::default());
+        encoder.write_all(&bytes).unwrap();
+        encoder.finish().unwrap();
+        let plain = dir.join("broken-idx1-ubyte");
+        fs::write(&plain, &bytes[..5]).unwrap();
+
+        let found = find_idx(&dir, &["labels-idx1-ubyte"]).unwrap();
+        assert_eq!(found, gz);
+        let labels = load_idx(found).unwrap().into_u8().unwrap();
+        assert_eq!(labels.into_raw_vec_and_offset().0, [7, 8, 9]);
+        let err = load_idx(&plain).unwrap_err();
+        assert!(err.to_string().starts_with(&plain.display().to_string()));
+        assert!(find_idx(&dir, &["missing"]).is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
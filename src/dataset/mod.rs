@@ -0,0 +1,6 @@
+pub mod augment;
+pub mod cifar;
+pub mod data_loader;
+pub mod error;
+pub mod idx;
+pub mod transform;
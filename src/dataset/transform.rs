@@ -0,0 +1,40 @@
+//! Transforms `DataLoader` applies to every batch of inputs as it is assembled.
+
+use ndarray::{Array, Dimension};
+use rand_chacha::ChaCha8Rng;
+
+/// Maps a batch of inputs to a batch of the same shape. `rng` is seeded for each batch, so the
+/// result does not depend on which thread assembles it.
+pub trait Transform<D: Dimension>: Send + Sync {
+    fn apply(&self, x: Array<f64, D>, rng: &mut ChaCha8Rng) -> Array<f64, D>;
+}
+
+impl<D, F> Transform<D> for F
+where
+    D: Dimension,
+    F: Fn(Array<f64, D>, &mut ChaCha8Rng) -> Array<f64, D> + Send + Sync,
+{
+    fn apply(&self, x: Array<f64, D>, rng: &mut ChaCha8Rng) -> Array<f64, D> {
+        self(x, rng)
+    }
+}
+
+/// `(x - mean) / std` for every element.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Normalize {
+    mean: f64,
+    std: f64,
+}
+
+impl Normalize {
+    pub fn new(mean: f64, std: f64) -> Self {
+        assert!(std > 0., "std must be positive");
+        Self { mean, std }
+    }
+}
+
+impl<D: Dimension> Transform<D> for Normalize {
+    fn apply(&self, x: Array<f64, D>, _rng: &mut ChaCha8Rng) -> Array<f64, D> {
+        x.mapv_into(|x| (x - self.mean) / self.std)
+    }
+}
@@ -19,10 +19,29 @@ use crate::{
 mod ch02;
 mod ch03;
 mod ch04;
+mod ch05;
+mod ch06;
+mod ch07;
+mod ch08;
+mod dataset;
+mod persistence;
 
 fn main() {
     // neuralnet_mnist_batch::run();
     // neuralnet_mnist::run();
+    // ch05::autograd::run();
+    // ch05::gradient_check::run();
+    // ch06::weight_decay::run();
+    // ch07::convolution::run();
+    // ch07::pooling::run();
+    // ch07::simple_convnet::run();
+    // ch08::deep_convnet::run();
+    // dataset::augment::run();
+    // dataset::cifar::run();
+    // dataset::idx::run();
+    // persistence::npy::run();
+    // persistence::params::run();
+    // persistence::safetensors::run();
     // let y = array![0.1,0.05,0.6,0.0,0.05,0.1,0.0,0.1,0.0,0.0];
     // let t = array![0.0,0.0,1.0,0.0,0.0,0.0,0.0,0.0,0.0,0.0];
     // let cee = cross_entropy_error(&y, &t);
@@ -60,4 +79,5 @@ fn main() {
     // println!("{:?}", dw);
 
     mini_batch();
+    // ch04::two_layer::mini_batch_resume();
 }
@@ -0,0 +1,238 @@
+//! Training checkpoints, stored as safetensors files (see `persistence::safetensors`).
+//!
+//! ```text
+//! tensors   params.<name>                 model parameters
+//!           optimizer.<slot>.<name>       per-parameter optimizer state, e.g. optimizer.m.w1
+//!           history.<list>                the TrainingHistory lists, 1-dimensional
+//! metadata  format, version               "checkpoint", "1"
+//!           iteration, epoch, learning_rate
+//!           optimizer.slots               comma-separated slot names
+//!           optimizer.<scalar>            e.g. optimizer.iter
+//!           scheduler.<key>               LrScheduler state
+//!           rng.seed, rng.stream, rng.word_pos
+//! ```
+//!
+//! Every tensor is stored as F64 and every number in the metadata is written with `to_string`,
+//! so nothing loses precision.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Write},
+    path::Path,
+    str::FromStr,
+};
+
+use ndarray::Array1;
+use ndarray_rand::rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+
+use crate::{
+    ch04::two_layer::Weight,
+    ch06::{optimizer::OptimizerState, trainer::TrainingHistory},
+    persistence::safetensors::{Dtype, read_safetensors, write_safetensors},
+};
+
+const FORMAT: &str = "checkpoint";
+const VERSION: &str = "1";
+
+fn invalid_data(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+/// Snapshot of a `Trainer` between two iterations.
+#[derive(Clone, Debug)]
+pub struct Checkpoint {
+    pub params: HashMap<String, Weight>,
+    pub optimizer: OptimizerState,
+    pub learning_rate: f64,
+    /// Empty when training without a scheduler.
+    pub scheduler: BTreeMap<String, f64>,
+    /// Mini-batch sampler, positioned to draw the order of the epoch in progress.
+    pub rng: ChaCha8Rng,
+    pub iteration: usize,
+    pub epoch: usize,
+    pub history: TrainingHistory,
+}
+
+impl Checkpoint {
+    pub fn write(&self, w: &mut impl Write) -> io::Result<()> {
+        let mut tensors = HashMap::new();
+        for (name, param) in &self.params {
+            tensors.insert(format!("params.{name}"), param.clone());
+        }
+        for (slot, states) in &self.optimizer.slots {
+            for (name, state) in states {
+                tensors.insert(
+                    format!("optimizer.{slot}.{name}"),
+                    Weight::from_dyn(state.clone()),
+                );
+            }
+        }
+        let history = &self.history;
+        for (name, list) in [
+            ("train_loss_list", &history.train_loss_list),
+            ("train_acc_list", &history.train_acc_list),
+            ("test_acc_list", &history.test_acc_list),
+        ] {
+            tensors.insert(
+                format!("history.{name}"),
+                Weight::M1(Array1::from(list.clone())),
+            );
+        }
+
+        let mut metadata = BTreeMap::from([
+            ("format".to_owned(), FORMAT.to_owned()),
+            ("version".to_owned(), VERSION.to_owned()),
+            ("iteration".to_owned(), self.iteration.to_string()),
+            ("epoch".to_owned(), self.epoch.to_string()),
+            ("learning_rate".to_owned(), self.learning_rate.to_string()),
+            (
+                "optimizer.slots".to_owned(),
+                self.optimizer
+                    .slots
+                    .keys()
+                    .cloned()
+                    .collect::<Vec<_>>()
+                    .join(","),
+            ),
+            (
+                "rng.seed".to_owned(),
+                self.rng
+                    .get_seed()
+                    .iter()
+                    .map(|b| format!("{b:02x}"))
+                    .collect(),
+            ),
+            ("rng.stream".to_owned(), self.rng.get_stream().to_string()),
+            (
+                "rng.word_pos".to_owned(),
+                self.rng.get_word_pos().to_string(),
+            ),
+        ]);
+        for (key, value) in &self.optimizer.scalars {
+            metadata.insert(format!("optimizer.{key}"), value.to_string());
+        }
+        for (key, value) in &self.scheduler {
+            metadata.insert(format!("scheduler.{key}"), value.to_string());
+        }
+
+        write_safetensors(w, &tensors, Dtype::F64, &metadata)
+    }
+
+    pub fn read(r: &mut impl io::Read) -> io::Result<Self> {
+        let (tensors, metadata) = read_safetensors(r)?;
+        let get = |key: &str| {
+            metadata
+                .get(key)
+                .ok_or_else(|| invalid_data(format!("checkpoint has no `{key}`")))
+        };
+        if get("format")? != FORMAT {
+            return Err(invalid_data("not a checkpoint"));
+        }
+        let version = get("version")?;
+        if version != VERSION {
+            return Err(invalid_data(format!(
+                "unsupported checkpoint version {version}"
+            )));
+        }
+
+        let mut optimizer = OptimizerState::default();
+        for slot in get("optimizer.slots")?.split(',').filter(|s| !s.is_empty()) {
+            optimizer.slots.insert(slot.to_owned(), HashMap::new());
+        }
+        let mut scheduler = BTreeMap::new();
+        for (key, value) in &metadata {
+            if let Some(name) = key.strip_prefix("scheduler.") {
+                scheduler.insert(name.to_owned(), parse(key, value)?);
+            } else if let Some(name) = key.strip_prefix("optimizer.")
+                && name != "slots"
+            {
+                optimizer
+                    .scalars
+                    .insert(name.to_owned(), parse(key, value)?);
+            }
+        }
+
+        let mut params = HashMap::new();
+        let mut history = HashMap::new();
+        for (key, tensor) in tensors {
+            let bad_key = || invalid_data(format!("unexpected tensor `{key}`"));
+            let (group, name) = key.split_once('.').ok_or_else(bad_key)?;
+            match group {
+                "params" => {
+                    params.insert(name.to_owned(), tensor);
+                }
+                "optimizer" => {
+                    let (slot, name) = name.split_once('.').ok_or_else(bad_key)?;
+                    let states = optimizer.slots.get_mut(slot).ok_or_else(bad_key)?;
+                    states.insert(name.to_owned(), tensor.view().to_owned());
+                }
+                "history" => {
+                    let Weight::M1(list) = tensor else {
+                        return Err(bad_key());
+                    };
+                    history.insert(name.to_owned(), list.to_vec());
+                }
+                _ => return Err(bad_key()),
+            }
+        }
+        let mut take_list = |name: &str| {
+            history
+                .remove(name)
+                .ok_or_else(|| invalid_data(format!("checkpoint has no `history.{name}`")))
+        };
+        let history = TrainingHistory {
+            train_loss_list: take_list("train_loss_list")?,
+            train_acc_list: take_list("train_acc_list")?,
+            test_acc_list: take_list("test_acc_list")?,
+        };
+
+        let seed = get("rng.seed")?;
+        let seed = (0..seed.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(seed.get(i..i + 2)?, 16).ok())
+            .collect::<Option<Vec<_>>>()
+            .and_then(|seed| <[u8; 32]>::try_from(seed).ok())
+            .ok_or_else(|| invalid_data(format!("bad `rng.seed`: {seed}")))?;
+        let mut rng = ChaCha8Rng::from_seed(seed);
+        rng.set_stream(parse("rng.stream", get("rng.stream")?)?);
+        rng.set_word_pos(parse("rng.word_pos", get("rng.word_pos")?)?);
+
+        Ok(Self {
+            params,
+            optimizer,
+            learning_rate: parse("learning_rate", get("learning_rate")?)?,
+            scheduler,
+            rng,
+            iteration: parse("iteration", get("iteration")?)?,
+            epoch: parse("epoch", get("epoch")?)?,
+            history,
+        })
+    }
+
+    /// Written to a temporary file first and then renamed over `path`, so an interrupted save
+    /// leaves the previous checkpoint intact. Missing parent directories are created.
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let path = path.as_ref();
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let tmp_path = path.with_extension("tmp");
+        let mut w = BufWriter::new(File::create(&tmp_path)?);
+        self.write(&mut w)?;
+        w.flush()?;
+        drop(w);
+        fs::rename(tmp_path, path)
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::read(&mut BufReader::new(File::open(path)?))
+    }
+}
+
+fn parse<T: FromStr>(key: &str, value: &str) -> io::Result<T> {
+    value
+        .parse()
+        .map_err(|_| invalid_data(format!("bad `{key}`: {value}")))
+}
@@ -0,0 +1,4 @@
+pub mod checkpoint;
+pub mod npy;
+pub mod params;
+pub mod safetensors;
@@ -0,0 +1,360 @@
+//! NumPy `.npy` and `.npz` files.
+//!
+//! Reading accepts little- and big-endian floats, signed and unsigned integers and bools of any
+//! shape, in C or Fortran order, and converts them to f64. Writing always produces
+//! little-endian f64 (`'<f8'`) in C order. An `.npz` is a zip archive with one `.npy` per array,
+//! stored or deflated, as written by `np.savez` and `np.savez_compressed`.
+
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Seek, Write},
+    path::Path,
+};
+
+use ndarray::{Array, ArrayBase, ArrayD, Data, Dimension, IxDyn, ShapeBuilder};
+use ndarray_rand::{RandomExt, rand_distr::StandardNormal};
+use zip::{ZipArchive, ZipWriter, write::SimpleFileOptions};
+
+use crate::{
+    ch04::two_layer::{TwoLayerNet, Weight},
+    ch05::model::Model,
+    ch06::weight_init::WeightInit,
+    persistence::params::{assign_params, data_len, read_bytes},
+};
+
+const MAGIC: &[u8; 6] = b"\x93NUMPY";
+
+fn invalid_data(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+fn zip_error(err: zip::result::ZipError) -> io::Error {
+    match err {
+        zip::result::ZipError::Io(err) => err,
+        err => invalid_data(err.to_string()),
+    }
+}
+
+/// Element type from a `descr` such as `'<f8'`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Descr {
+    Float { size: usize, little: bool },
+    Int { size: usize, little: bool },
+    Uint { size: usize, little: bool },
+    Bool,
+}
+
+impl Descr {
+    fn parse(descr: &str) -> io::Result<Self> {
+        let unsupported = || invalid_data(format!("unsupported dtype '{descr}'"));
+        let mut chars = descr.chars();
+        let little = match chars.next() {
+            Some('<' | '|' | '=') => true,
+            Some('>') => false,
+            _ => return Err(unsupported()),
+        };
+        let kind = chars.next().ok_or_else(unsupported)?;
+        let size = chars.as_str().parse::<usize>().map_err(|_| unsupported())?;
+        match (kind, size) {
+            ('f', 4 | 8) => Ok(Self::Float { size, little }),
+            ('i', 1 | 2 | 4 | 8) => Ok(Self::Int { size, little }),
+            ('u', 1 | 2 | 4 | 8) => Ok(Self::Uint { size, little }),
+            ('b', 1) => Ok(Self::Bool),
+            _ => Err(unsupported()),
+        }
+    }
+
+    fn size(&self) -> usize {
+        match *self {
+            Self::Float { size, .. } | Self::Int { size, .. } | Self::Uint { size, .. } => size,
+            Self::Bool => 1,
+        }
+    }
+
+    fn to_f64(self, bytes: &[u8]) -> f64 {
+        // widen to 8 bytes in the file's byte order, then decode once
+        let little = match self {
+            Self::Float { little, .. } | Self::Int { little, .. } | Self::Uint { little, .. } => {
+                little
+            }
+            Self::Bool => true,
+        };
+        let mut buf = [0; 8];
+        let n = bytes.len();
+        if little {
+            buf[..n].copy_from_slice(bytes);
+        } else {
+            buf[8 - n..].copy_from_slice(bytes);
+        }
+        let raw = if little {
+            u64::from_le_bytes(buf)
+        } else {
+            u64::from_be_bytes(buf)
+        };
+
+        match self {
+            Self::Float { size: 8, .. } => f64::from_bits(raw),
+            Self::Float { .. } => f32::from_bits(raw as u32) as f64,
+            Self::Int { size, .. } => {
+                // sign-extend from `size` bytes
+                let shift = 64 - 8 * size as u32;
+                ((raw << shift) as i64 >> shift) as f64
+            }
+            Self::Uint { .. } => raw as f64,
+            Self::Bool => (raw != 0) as u8 as f64,
+        }
+    }
+}
+
+/// Value of `'key':` in the header dict, up to the next top-level comma.
+fn header_value<'h>(header: &'h str, key: &str) -> io::Result<&'h str> {
+    let missing = || invalid_data(format!("header has no '{key}'"));
+    let start = header.find(&format!("'{key}'")).ok_or_else(missing)?;
+    let rest = &header[start + key.len() + 2..];
+    let rest = rest
+        .trim_start()
+        .strip_prefix(':')
+        .ok_or_else(missing)?
+        .trim_start();
+    let end = if rest.starts_with('(') {
+        rest.find(')').map(|i| i + 1)
+    } else {
+        rest.find([',', '}'])
+    };
+    Ok(rest[..end.ok_or_else(missing)?].trim())
+}
+
+/// Read one `.npy` array.
+pub fn read_npy(r: &mut impl Read) -> io::Result<ArrayD<f64>> {
+    let mut magic = [0; 6];
+    r.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(invalid_data("not a .npy file (bad magic)"));
+    }
+    let mut version = [0; 2];
+    r.read_exact(&mut version)?;
+    let header_len = match version[0] {
+        1 => {
+            let mut buf = [0; 2];
+            r.read_exact(&mut buf)?;
+            u16::from_le_bytes(buf) as usize
+        }
+        2 | 3 => {
+            let mut buf = [0; 4];
+            r.read_exact(&mut buf)?;
+            u32::from_le_bytes(buf) as usize
+        }
+        major => return Err(invalid_data(format!("unsupported .npy version {major}"))),
+    };
+    let header = String::from_utf8(read_bytes(r, header_len)?)
+        .map_err(|_| invalid_data("header is not UTF-8"))?;
+
+    let descr = Descr::parse(header_value(&header, "descr")?.trim_matches(['\'', '"']))?;
+    let fortran_order = match header_value(&header, "fortran_order")? {
+        "True" => true,
+        "False" => false,
+        other => return Err(invalid_data(format!("bad fortran_order {other}"))),
+    };
+    let shape = header_value(&header, "shape")?
+        .trim_matches(['(', ')'])
+        .split(',')
+        .map(str::trim)
+        .filter(|dim| !dim.is_empty())
+        .map(|dim| {
+            dim.parse::<usize>()
+                .map_err(|_| invalid_data(format!("bad dimension {dim}")))
+        })
+        .collect::<io::Result<Vec<_>>>()?;
+
+    let data = read_bytes(r, data_len(&shape, descr.size())?)?
+        .chunks_exact(descr.size())
+        .map(|b| descr.to_f64(b))
+        .collect();
+
+    let shape = IxDyn(&shape);
+    let array = if fortran_order {
+        ArrayD::from_shape_vec(shape.f(), data)
+    } else {
+        ArrayD::from_shape_vec(shape, data)
+    };
+    Ok(array.unwrap())
+}
+
+/// Write `array` as a version 1.0 `.npy` with dtype `'<f8'`.
+pub fn write_npy<S, D>(w: &mut impl Write, array: &ArrayBase<S, D>) -> io::Result<()>
+where
+    S: Data<Elem = f64>,
+    D: Dimension,
+{
+    let shape = match array.shape() {
+        [dim] => format!("({dim},)"),
+        dims => format!(
+            "({})",
+            dims.iter()
+                .map(usize::to_string)
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    };
+    let mut header = format!("{{'descr': '<f8', 'fortran_order': False, 'shape': {shape}, }}");
+    // magic (6) + version (2) + header_len (2) + header + '\n' is padded to a multiple of 64
+    let total = 10 + header.len() + 1;
+    header.push_str(&" ".repeat(total.next_multiple_of(64) - total));
+    header.push('\n');
+    let header_len = u16::try_from(header.len()).map_err(|_| invalid_data("shape too long"))?;
+
+    w.write_all(MAGIC)?;
+    w.write_all(&[1, 0])?;
+    w.write_all(&header_len.to_le_bytes())?;
+    w.write_all(header.as_bytes())?;
+    for &x in array.iter() {
+        w.write_all(&x.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+pub fn load_npy(path: impl AsRef<Path>) -> io::Result<ArrayD<f64>> {
+    read_npy(&mut BufReader::new(File::open(path)?))
+}
+
+pub fn save_npy<S, D>(path: impl AsRef<Path>, array: &ArrayBase<S, D>) -> io::Result<()>
+where
+    S: Data<Elem = f64>,
+    D: Dimension,
+{
+    let mut w = BufWriter::new(File::create(path)?);
+    write_npy(&mut w, array)?;
+    w.flush()
+}
+
+/// Every array of an `.npz` archive, keyed by its name without the `.npy` suffix.
+pub fn read_npz(r: impl Read + Seek) -> io::Result<HashMap<String, ArrayD<f64>>> {
+    let mut archive = ZipArchive::new(r).map_err(zip_error)?;
+    let mut arrays = HashMap::new();
+    for i in 0..archive.len() {
+        let mut file = archive.by_index(i).map_err(zip_error)?;
+        let name = file.name().trim_end_matches(".npy").to_owned();
+        let array = read_npy(&mut file)
+            .map_err(|err| io::Error::new(err.kind(), format!("{name}: {err}")))?;
+        arrays.insert(name, array);
+    }
+    Ok(arrays)
+}
+
+/// Write `arrays` as an uncompressed `.npz`, like `np.savez`. Entries are sorted by name.
+pub fn write_npz<'a, S, D>(
+    w: impl Write + Seek,
+    arrays: impl IntoIterator<Item = (&'a str, &'a ArrayBase<S, D>)>,
+) -> io::Result<()>
+where
+    S: Data<Elem = f64> + 'a,
+    D: Dimension + 'a,
+{
+    let mut arrays = arrays.into_iter().collect::<Vec<_>>();
+    arrays.sort_by_key(|(name, _)| *name);
+
+    let mut zip = ZipWriter::new(w);
+    let options = SimpleFileOptions::default()
+        .compression_method(zip::CompressionMethod::Stored)
+        .large_file(true);
+    for (name, array) in arrays {
+        zip.start_file(format!("{name}.npy"), options)
+            .map_err(zip_error)?;
+        write_npy(&mut zip, array)?;
+    }
+    zip.finish().map_err(zip_error)?;
+    Ok(())
+}
+
+pub fn load_npz(path: impl AsRef<Path>) -> io::Result<HashMap<String, ArrayD<f64>>> {
+    read_npz(BufReader::new(File::open(path)?))
+}
+
+pub fn save_npz<'a, S, D>(
+    path: impl AsRef<Path>,
+    arrays: impl IntoIterator<Item = (&'a str, &'a ArrayBase<S, D>)>,
+) -> io::Result<()>
+where
+    S: Data<Elem = f64> + 'a,
+    D: Dimension + 'a,
+{
+    write_npz(BufWriter::new(File::create(path)?), arrays)
+}
+
+/// Parameter map from an `.npz`. Every array must be 1, 2 or 4-dimensional.
+pub fn load_npz_params(path: impl AsRef<Path>) -> io::Result<HashMap<String, Weight>> {
+    load_npz(path)?
+        .into_iter()
+        .map(|(name, array)| match array.ndim() {
+            1 | 2 | 4 => Ok((name, Weight::from_dyn(array))),
+            n => Err(invalid_data(format!(
+                "`{name}` has {n} dimensions, which no Weight variant holds"
+            ))),
+        })
+        .collect()
+}
+
+pub fn save_npz_params(path: impl AsRef<Path>, params: &HashMap<String, Weight>) -> io::Result<()> {
+    let arrays = params
+        .iter()
+        .map(|(name, param)| (name.as_str(), param.view()))
+        .collect::<Vec<_>>();
+    save_npz(path, arrays.iter().map(|(name, array)| (*name, array)))
+}
+
+/// Export a `TwoLayerNet` to `.npz` and load it back into a fresh one.
+pub fn run() {
+    let path = std::env::temp_dir().join("two_layer_net.npz");
+    let x = Array::random((4, 784), StandardNormal);
+
+    let mut network = TwoLayerNet::new(784, 50, 10, WeightInit::XavierNormal);
+    save_npz_params(&path, network.params()).unwrap();
+    println!("saved {}", path.display());
+
+    let mut loaded = TwoLayerNet::new(784, 50, 10, WeightInit::XavierNormal);
+    assign_params(loaded.params_mut(), load_npz_params(&path).unwrap()).unwrap();
+    println!(
+        "predictions equal after reload: {}",
+        Model::predict(&mut network, &x) == Model::predict(&mut loaded, &x)
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A version 1.0 `.npy` header for `'<f8'` data of `shape`, followed by `data`.
+    fn npy_with_shape(shape: &str, data: &[u8]) -> Vec<u8> {
+        let header = format!("{{'descr': '<f8', 'fortran_order': False, 'shape': {shape}, }}\n");
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend([1, 0]);
+        bytes.extend((header.len() as u16).to_le_bytes());
+        bytes.extend(header.bytes());
+        bytes.extend(data);
+        bytes
+    }
+
+    #[test]
+    fn rejects_overflowing_shape() {
+        let bytes = npy_with_shape(&format!("({}, 2)", usize::MAX), &[]);
+        let err = read_npy(&mut bytes.as_slice()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains("too large"), "{err}");
+    }
+
+    #[test]
+    fn rejects_truncated_data_without_allocating_it() {
+        let bytes = npy_with_shape("(1048576, 1024)", &[0; 16]);
+        let err = read_npy(&mut bytes.as_slice()).unwrap_err();
+        assert!(err.to_string().contains("truncated"), "{err}");
+    }
+
+    #[test]
+    fn reads_what_it_writes() {
+        let array = Array::random((3, 2), StandardNormal);
+        let mut bytes = Vec::new();
+        write_npy(&mut bytes, &array).unwrap();
+        assert_eq!(read_npy(&mut bytes.as_slice()).unwrap(), array.into_dyn());
+    }
+}
@@ -0,0 +1,375 @@
+//! Binary format for a parameter map (`HashMap<String, Weight>`).
+//!
+//! All integers and floats are little-endian.
+//!
+//! ```text
+//! magic       4 bytes   b"DLFS"
+//! version     u32       1
+//! count       u32       number of entries
+//! count times, sorted by name:
+//!   name_len  u32
+//!   name      name_len bytes of UTF-8
+//!   dtype     u8        0 = f64, 1 = f32
+//!   ndim      u32
+//!   shape     ndim x u64
+//!   data      product(shape) elements of dtype, row-major
+//! ```
+//!
+//! `save_params` always writes f64. `load_params` also reads f32 and widens it to f64.
+//! Only learnable parameters are stored; BatchNormalization running statistics are not.
+
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::Path,
+};
+
+use ndarray::{Array, ArrayD, IxDyn};
+use ndarray_rand::{RandomExt, rand_distr::StandardNormal};
+
+use crate::{
+    ch04::two_layer::{TwoLayerNet, Weight},
+    ch05::model::Model,
+    ch06::{
+        multi_layer_net::{Activation, MultiLayerNet},
+        weight_init::WeightInit,
+    },
+    ch07::simple_convnet::{ConvParam, SimpleConvNet},
+    ch08::deep_convnet::DeepConvNet,
+};
+
+const MAGIC: &[u8; 4] = b"DLFS";
+const VERSION: u32 = 1;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Dtype {
+    F64 = 0,
+    F32 = 1,
+}
+
+impl Dtype {
+    fn from_code(code: u8) -> io::Result<Self> {
+        match code {
+            0 => Ok(Self::F64),
+            1 => Ok(Self::F32),
+            _ => Err(invalid_data(format!("unknown dtype code {code}"))),
+        }
+    }
+}
+
+fn invalid_data(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+fn read_u8(r: &mut impl Read) -> io::Result<u8> {
+    let mut buf = [0; 1];
+    r.read_exact(&mut buf)?;
+    Ok(buf[0])
+}
+
+fn read_u32(r: &mut impl Read) -> io::Result<u32> {
+    let mut buf = [0; 4];
+    r.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_u64(r: &mut impl Read) -> io::Result<u64> {
+    let mut buf = [0; 8];
+    r.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+/// Bytes taken by an array of `shape` with `size`-byte elements. Fails instead of overflowing.
+pub(crate) fn data_len(shape: &[usize], size: usize) -> io::Result<usize> {
+    shape
+        .iter()
+        .try_fold(size, |len: usize, &dim| len.checked_mul(dim))
+        .ok_or_else(|| invalid_data(format!("shape {shape:?} is too large")))
+}
+
+/// Exactly `len` bytes. Read incrementally rather than trusting a header with one large
+/// allocation, so a corrupt length fails at the end of the input.
+pub(crate) fn read_bytes(r: &mut impl Read, len: usize) -> io::Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    r.by_ref().take(len as u64).read_to_end(&mut buf)?;
+    if buf.len() != len {
+        return Err(invalid_data(format!(
+            "truncated data: expected {len} bytes, found {}",
+            buf.len()
+        )));
+    }
+    Ok(buf)
+}
+
+/// Serialize `params` into `w` in the format described at the top of this module.
+pub fn write_params(w: &mut impl Write, params: &HashMap<String, Weight>) -> io::Result<()> {
+    let mut names = params.keys().collect::<Vec<_>>();
+    names.sort();
+
+    w.write_all(MAGIC)?;
+    w.write_all(&VERSION.to_le_bytes())?;
+    w.write_all(&(names.len() as u32).to_le_bytes())?;
+    for name in names {
+        let param = params[name].view();
+        w.write_all(&(name.len() as u32).to_le_bytes())?;
+        w.write_all(name.as_bytes())?;
+        w.write_all(&[Dtype::F64 as u8])?;
+        w.write_all(&(param.ndim() as u32).to_le_bytes())?;
+        for &dim in param.shape() {
+            w.write_all(&(dim as u64).to_le_bytes())?;
+        }
+        // `iter` walks in logical (row-major) order whatever the memory layout is
+        for &x in param.iter() {
+            w.write_all(&x.to_le_bytes())?;
+        }
+    }
+    Ok(())
+}
+
+/// Inverse of `write_params`.
+pub fn read_params(r: &mut impl Read) -> io::Result<HashMap<String, Weight>> {
+    let mut magic = [0; 4];
+    r.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(invalid_data("not a parameter file (bad magic)"));
+    }
+    let version = read_u32(r)?;
+    if version != VERSION {
+        return Err(invalid_data(format!("unsupported version {version}")));
+    }
+
+    let count = read_u32(r)?;
+    let mut params = HashMap::new();
+    for _ in 0..count {
+        let name_len = read_u32(r)? as usize;
+        let name = String::from_utf8(read_bytes(r, name_len)?)
+            .map_err(|_| invalid_data("name is not UTF-8"))?;
+
+        let dtype = Dtype::from_code(read_u8(r)?)?;
+        let ndim = read_u32(r)? as usize;
+        if !matches!(ndim, 1 | 2 | 4) {
+            return Err(invalid_data(format!(
+                "`{name}` has {ndim} dimensions, which no Weight variant holds"
+            )));
+        }
+        let shape = (0..ndim)
+            .map(|_| read_u64(r).map(|dim| dim as usize))
+            .collect::<io::Result<Vec<_>>>()?;
+
+        let data = match dtype {
+            Dtype::F64 => read_bytes(r, data_len(&shape, 8)?)?
+                .chunks_exact(8)
+                .map(|b| f64::from_le_bytes(b.try_into().unwrap()))
+                .collect(),
+            Dtype::F32 => read_bytes(r, data_len(&shape, 4)?)?
+                .chunks_exact(4)
+                .map(|b| f32::from_le_bytes(b.try_into().unwrap()) as f64)
+                .collect::<Vec<_>>(),
+        };
+        let param = ArrayD::from_shape_vec(IxDyn(&shape), data).unwrap();
+        if params
+            .insert(name.clone(), Weight::from_dyn(param))
+            .is_some()
+        {
+            return Err(invalid_data(format!("duplicate parameter `{name}`")));
+        }
+    }
+    Ok(params)
+}
+
+pub fn save_params(path: impl AsRef<Path>, params: &HashMap<String, Weight>) -> io::Result<()> {
+    let mut w = BufWriter::new(File::create(path)?);
+    write_params(&mut w, params)?;
+    w.flush()
+}
+
+pub fn load_params(path: impl AsRef<Path>) -> io::Result<HashMap<String, Weight>> {
+    read_params(&mut BufReader::new(File::open(path)?))
+}
+
+/// Replace every parameter of `params` with the one of the same name in `loaded`.
+/// Fails without touching `params` if a name is missing or a shape differs.
+pub fn assign_params(
+    params: &mut HashMap<String, Weight>,
+    mut loaded: HashMap<String, Weight>,
+) -> io::Result<()> {
+    for (name, param) in params.iter() {
+        let Some(new) = loaded.get(name) else {
+            return Err(invalid_data(format!("missing parameter `{name}`")));
+        };
+        if new.view().shape() != param.view().shape() {
+            return Err(invalid_data(format!(
+                "`{name}` has shape {:?}, expected {:?}",
+                new.view().shape(),
+                param.view().shape()
+            )));
+        }
+    }
+    for (name, param) in params.iter_mut() {
+        *param = loaded.remove(name).unwrap();
+    }
+    Ok(())
+}
+
+/// Save every model type, load it into a freshly initialized copy and compare predictions.
+pub fn run() {
+    let dir = std::env::temp_dir();
+
+    let two_layer = || TwoLayerNet::new(784, 50, 10, WeightInit::XavierNormal);
+    let multi_layer = || {
+        MultiLayerNet::new(784, &[100, 50], 10, Activation::Relu, WeightInit::HeNormal)
+            .with_batchnorm()
+    };
+    let x = Array::random((4, 784), StandardNormal);
+    round_trip("TwoLayerNet", two_layer(), two_layer(), &x, &dir);
+    round_trip("MultiLayerNet", multi_layer(), multi_layer(), &x, &dir);
+
+    let simple_conv = || {
+        SimpleConvNet::new(
+            (1, 28, 28),
+            ConvParam::default(),
+            100,
+            10,
+            WeightInit::HeNormal,
+        )
+    };
+    let deep_conv = || DeepConvNet::new((1, 28, 28), 50, 10, WeightInit::HeNormal);
+    let x = Array::random((4, 1, 28, 28), StandardNormal);
+    round_trip("SimpleConvNet", simple_conv(), simple_conv(), &x, &dir);
+    round_trip("DeepConvNet", deep_conv(), deep_conv(), &x, &dir);
+}
+
+fn round_trip<M>(name: &str, mut model: M, mut fresh: M, x: &M::Input, dir: &Path)
+where
+    M: Model,
+{
+    let path = dir.join(format!("{name}.params"));
+    model.save_params(&path).unwrap();
+
+    let y = model.predict(x);
+    let differs_before = y != fresh.predict(x);
+    fresh.load_params(&path).unwrap();
+    let equal_after = y == fresh.predict(x);
+    println!("{name}: differs before load: {differs_before} | equal after load: {equal_after}");
+}
+
+#[cfg(test)]
+mod tests {
+    use ndarray::{Array2, Array4};
+
+    use super::*;
+
+    /// Save `model`, load it into `fresh` and check that every parameter came back unchanged.
+    fn assert_round_trip<M: Model>(name: &str, model: M, mut fresh: M, x: &M::Input) {
+        let path = std::env::temp_dir().join(format!("params_test_{name}.params"));
+        model.save_params(&path).unwrap();
+        fresh.load_params(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let (saved, loaded) = (model.params(), fresh.params());
+        let mut keys = saved.keys().collect::<Vec<_>>();
+        keys.sort();
+        let mut loaded_keys = loaded.keys().collect::<Vec<_>>();
+        loaded_keys.sort();
+        assert_eq!(keys, loaded_keys);
+        for key in keys {
+            let (saved, loaded) = (saved[key].view(), loaded[key].view());
+            assert_eq!(saved.shape(), loaded.shape(), "shape of `{key}`");
+            assert_eq!(saved, loaded, "values of `{key}`");
+        }
+
+        let mut model = model;
+        model.eval();
+        fresh.eval();
+        assert_eq!(model.predict(x), fresh.predict(x));
+    }
+
+    #[test]
+    fn two_layer_net_round_trip() {
+        let net = || TwoLayerNet::new(20, 8, 3, WeightInit::XavierNormal);
+        let x: Array2<f64> = Array::random((2, 20), StandardNormal);
+        assert_round_trip("two_layer", net(), net(), &x);
+    }
+
+    #[test]
+    fn multi_layer_net_round_trip() {
+        let net = || {
+            MultiLayerNet::new(20, &[10, 6], 3, Activation::Relu, WeightInit::HeNormal)
+                .with_batchnorm()
+        };
+        let x: Array2<f64> = Array::random((2, 20), StandardNormal);
+        assert_round_trip("multi_layer", net(), net(), &x);
+    }
+
+    #[test]
+    fn simple_conv_net_round_trip() {
+        let net = || {
+            SimpleConvNet::new(
+                (1, 10, 10),
+                ConvParam::default(),
+                8,
+                3,
+                WeightInit::HeNormal,
+            )
+        };
+        let x: Array4<f64> = Array::random((2, 1, 10, 10), StandardNormal);
+        assert_round_trip("simple_conv", net(), net(), &x);
+    }
+
+    #[test]
+    fn deep_conv_net_round_trip() {
+        let net = || DeepConvNet::new((1, 28, 28), 8, 3, WeightInit::HeNormal);
+        let x: Array4<f64> = Array::random((1, 1, 28, 28), StandardNormal);
+        assert_round_trip("deep_conv", net(), net(), &x);
+    }
+
+    /// A file with one f64 parameter named "w" of `shape`, followed by `data`.
+    fn file_with_shape(shape: &[u64], data: &[u8]) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend(VERSION.to_le_bytes());
+        bytes.extend(1_u32.to_le_bytes());
+        bytes.extend(1_u32.to_le_bytes());
+        bytes.push(b'w');
+        bytes.push(Dtype::F64 as u8);
+        bytes.extend((shape.len() as u32).to_le_bytes());
+        for dim in shape {
+            bytes.extend(dim.to_le_bytes());
+        }
+        bytes.extend(data);
+        bytes
+    }
+
+    #[test]
+    fn rejects_overflowing_shape() {
+        let bytes = file_with_shape(&[u64::MAX, 2], &[]);
+        let err = read_params(&mut bytes.as_slice()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains("too large"), "{err}");
+    }
+
+    #[test]
+    fn rejects_truncated_data_without_allocating_it() {
+        // claims 8 GB of data but holds 16 bytes
+        let bytes = file_with_shape(&[1 << 20, 1 << 10], &[0; 16]);
+        let err = read_params(&mut bytes.as_slice()).unwrap_err();
+        assert!(err.to_string().contains("truncated"), "{err}");
+    }
+
+    #[test]
+    fn reads_what_it_writes() {
+        let params = HashMap::from([
+            ("b".to_owned(), Weight::M1(Array::from(vec![1., -2.]))),
+            (
+                "w".to_owned(),
+                Weight::M2(Array::random((3, 2), StandardNormal)),
+            ),
+        ]);
+        let mut bytes = Vec::new();
+        write_params(&mut bytes, &params).unwrap();
+        let read = read_params(&mut bytes.as_slice()).unwrap();
+        assert_eq!(read["b"].view(), params["b"].view());
+        assert_eq!(read["w"].view(), params["w"].view());
+    }
+}
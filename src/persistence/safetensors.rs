@@ -0,0 +1,232 @@
+//! Parameter maps in the safetensors layout (https://github.com/huggingface/safetensors).
+//!
+//! ```text
+//! header_size  u64 little-endian
+//! header       header_size bytes of JSON, padded with spaces to a multiple of 8:
+//!              {"__metadata__": {"key": "value", ...},
+//!               "<name>": {"dtype": "F64", "shape": [..], "data_offsets": [begin, end]}, ...}
+//! buffer       the tensors back to back, row-major and little-endian;
+//!              offsets are relative to the start of the buffer
+//! ```
+//!
+//! Only `F32` and `F64` tensors of 1, 2 or 4 dimensions can be read into a `Weight`.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::Path,
+};
+
+use ndarray::{Array, ArrayD, IxDyn};
+use ndarray_rand::{RandomExt, rand_distr::StandardNormal};
+use serde_json::{Map, Value, json};
+
+use crate::{
+    ch04::two_layer::Weight,
+    ch05::model::Model,
+    ch06::{
+        multi_layer_net::{Activation, MultiLayerNet},
+        weight_init::WeightInit,
+    },
+    persistence::params::assign_params,
+};
+
+/// Refuse headers larger than this, as the reference implementation does.
+const MAX_HEADER_SIZE: u64 = 100_000_000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Dtype {
+    F32,
+    F64,
+}
+
+impl Dtype {
+    fn name(&self) -> &'static str {
+        match self {
+            Self::F32 => "F32",
+            Self::F64 => "F64",
+        }
+    }
+
+    fn size(&self) -> usize {
+        match self {
+            Self::F32 => 4,
+            Self::F64 => 8,
+        }
+    }
+}
+
+fn invalid_data(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+/// Serialize `params` with every tensor stored as `dtype`. Tensors are laid out sorted by name.
+pub fn write_safetensors(
+    w: &mut impl Write,
+    params: &HashMap<String, Weight>,
+    dtype: Dtype,
+    metadata: &BTreeMap<String, String>,
+) -> io::Result<()> {
+    let mut names = params.keys().collect::<Vec<_>>();
+    names.sort();
+
+    let mut header = Map::new();
+    if !metadata.is_empty() {
+        header.insert("__metadata__".to_owned(), json!(metadata));
+    }
+    let mut offset = 0;
+    for name in &names {
+        let param = params[*name].view();
+        let end = offset + param.len() * dtype.size();
+        header.insert(
+            (*name).clone(),
+            json!({
+                "dtype": dtype.name(),
+                "shape": param.shape(),
+                "data_offsets": [offset, end],
+            }),
+        );
+        offset = end;
+    }
+
+    let mut header = serde_json::to_string(&header).map_err(io::Error::other)?;
+    header.push_str(&" ".repeat(header.len().next_multiple_of(8) - header.len()));
+
+    w.write_all(&(header.len() as u64).to_le_bytes())?;
+    w.write_all(header.as_bytes())?;
+    for name in names {
+        for &x in params[name].view().iter() {
+            match dtype {
+                Dtype::F32 => w.write_all(&(x as f32).to_le_bytes())?,
+                Dtype::F64 => w.write_all(&x.to_le_bytes())?,
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Inverse of `write_safetensors`. Returns the tensors and the `__metadata__` entries.
+pub fn read_safetensors(
+    r: &mut impl Read,
+) -> io::Result<(HashMap<String, Weight>, BTreeMap<String, String>)> {
+    let mut buf = [0; 8];
+    r.read_exact(&mut buf)?;
+    let header_size = u64::from_le_bytes(buf);
+    if header_size > MAX_HEADER_SIZE {
+        return Err(invalid_data(format!(
+            "header of {header_size} bytes is too large"
+        )));
+    }
+    let mut header = vec![0; header_size as usize];
+    r.read_exact(&mut header)?;
+    let header: Map<String, Value> = serde_json::from_slice(&header)
+        .map_err(|err| invalid_data(format!("bad header: {err}")))?;
+
+    let mut buffer = Vec::new();
+    r.read_to_end(&mut buffer)?;
+
+    let mut metadata = BTreeMap::new();
+    let mut tensors = Vec::new();
+    for (name, info) in header {
+        if name == "__metadata__" {
+            metadata = serde_json::from_value(info)
+                .map_err(|err| invalid_data(format!("bad __metadata__: {err}")))?;
+            continue;
+        }
+        tensors.push((name, info));
+    }
+
+    let mut params = HashMap::new();
+    for (name, info) in tensors {
+        let bad = |what: &str| invalid_data(format!("`{name}`: {what}"));
+        let dtype = match info["dtype"].as_str() {
+            Some("F32") => Dtype::F32,
+            Some("F64") => Dtype::F64,
+            Some(other) => return Err(bad(&format!("unsupported dtype {other}"))),
+            None => return Err(bad("missing dtype")),
+        };
+        let shape = info["shape"]
+            .as_array()
+            .and_then(|shape| {
+                shape
+                    .iter()
+                    .map(|dim| dim.as_u64().map(|dim| dim as usize))
+                    .collect::<Option<Vec<_>>>()
+            })
+            .ok_or_else(|| bad("bad shape"))?;
+        let [begin, end] = info["data_offsets"]
+            .as_array()
+            .and_then(|offsets| match offsets.as_slice() {
+                [begin, end] => Some([begin.as_u64()? as usize, end.as_u64()? as usize]),
+                _ => None,
+            })
+            .ok_or_else(|| bad("bad data_offsets"))?;
+
+        let len = shape.iter().product::<usize>();
+        if begin > end || end > buffer.len() || end - begin != len * dtype.size() {
+            return Err(bad("data_offsets do not match shape and dtype"));
+        }
+        if !matches!(shape.len(), 1 | 2 | 4) {
+            return Err(bad(&format!(
+                "{} dimensions, which no Weight variant holds",
+                shape.len()
+            )));
+        }
+
+        let bytes = &buffer[begin..end];
+        let data = match dtype {
+            Dtype::F32 => bytes
+                .chunks_exact(4)
+                .map(|b| f32::from_le_bytes(b.try_into().unwrap()) as f64)
+                .collect(),
+            Dtype::F64 => bytes
+                .chunks_exact(8)
+                .map(|b| f64::from_le_bytes(b.try_into().unwrap()))
+                .collect(),
+        };
+        let param = ArrayD::from_shape_vec(IxDyn(&shape), data).unwrap();
+        params.insert(name, Weight::from_dyn(param));
+    }
+    Ok((params, metadata))
+}
+
+pub fn save_safetensors(
+    path: impl AsRef<Path>,
+    params: &HashMap<String, Weight>,
+    dtype: Dtype,
+) -> io::Result<()> {
+    let mut w = BufWriter::new(File::create(path)?);
+    write_safetensors(&mut w, params, dtype, &BTreeMap::new())?;
+    w.flush()
+}
+
+pub fn load_safetensors(path: impl AsRef<Path>) -> io::Result<HashMap<String, Weight>> {
+    let (params, _) = read_safetensors(&mut BufReader::new(File::open(path)?))?;
+    Ok(params)
+}
+
+/// Round-trip a `MultiLayerNet` through both dtypes.
+pub fn run() {
+    let x = Array::random((4, 784), StandardNormal);
+    let new_network =
+        || MultiLayerNet::new(784, &[100, 50], 10, Activation::Relu, WeightInit::HeNormal);
+    let mut network = new_network();
+    let y = network.predict(&x);
+
+    for dtype in [Dtype::F64, Dtype::F32] {
+        let path =
+            std::env::temp_dir().join(format!("multi_layer_net_{}.safetensors", dtype.name()));
+        save_safetensors(&path, network.params(), dtype).unwrap();
+
+        let mut loaded = new_network();
+        assign_params(loaded.params_mut(), load_safetensors(&path).unwrap()).unwrap();
+        let max_diff = (&y - &loaded.predict(&x))
+            .mapv(f64::abs)
+            .fold(0., |a: f64, &b| a.max(b));
+        println!(
+            "{dtype:?}: {} | max prediction difference {max_diff:e}",
+            path.display()
+        );
+    }
+}